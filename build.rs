@@ -1,9 +1,70 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
+use serde::Deserialize;
+
+#[path = "build/reflect.rs"]
+mod reflect;
+
+// shared with the application so values like the workgroup size are defined once
+#[allow(dead_code)]
+#[path = "src/constants.rs"]
+mod constants;
+
+const SHADERS_DIR: &str = "src/shaders";
+const SHADER_MANIFEST: &str = "src/shaders/shaders.toml";
+
+#[derive(Deserialize, Default)]
+struct ShaderManifest {
+    #[serde(default)]
+    variant: Vec<ShaderVariantDesc>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct ShaderVariantDesc {
+    name: String,
+    source: String,
+    #[serde(default)]
+    defines: BTreeMap<String, String>,
+    #[serde(default)]
+    optimization: Optimization,
+    #[serde(default)]
+    debug_info: bool,
+    #[serde(default)]
+    kernel: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Optimization {
+    Zero,
+    Size,
+    #[default]
+    Performance,
+}
+
+impl From<Optimization> for shaderc::OptimizationLevel {
+    fn from(optimization: Optimization) -> Self {
+        match optimization {
+            Optimization::Zero => shaderc::OptimizationLevel::Zero,
+            Optimization::Size => shaderc::OptimizationLevel::Size,
+            Optimization::Performance => shaderc::OptimizationLevel::Performance,
+        }
+    }
+}
+
+struct CompiledVariant {
+    name: String,
+    kernel: String,
+    spv_path: String,
+    interface: reflect::ShaderInterface,
+}
+
 fn visit_dirs(
     dir: &Path,
-    cb: &dyn Fn(&std::path::PathBuf, shaderc::ShaderKind),
+    cb: &mut dyn FnMut(&std::path::PathBuf, shaderc::ShaderKind),
 ) -> std::io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
@@ -41,17 +102,202 @@ fn get_shader_kind(path_buf: &std::path::PathBuf) -> Option<shaderc::ShaderKind>
     }
 }
 
-fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKind) {
+fn read_manifest() -> ShaderManifest {
+    let manifest_path = Path::new(SHADER_MANIFEST);
+
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+
+    if !manifest_path.exists() {
+        return ShaderManifest::default();
+    }
+
+    let manifest_str = fs::read_to_string(manifest_path)
+        .unwrap_or_else(|_| panic!("failed to read shader manifest {:?}", manifest_path));
+
+    let manifest: ShaderManifest = toml::from_str(&manifest_str).unwrap_or_else(|err| {
+        panic!(
+            "failed to parse shader manifest {:?}: {}",
+            manifest_path, err
+        )
+    });
+
+    let mut names = std::collections::HashSet::new();
+    for variant in &manifest.variant {
+        assert!(
+            is_type_name(&variant.name),
+            "shader variant name {:?} is not a valid CamelCase identifier",
+            variant.name
+        );
+        assert!(
+            names.insert(variant.name.clone()),
+            "shader variant {:?} is declared more than once",
+            variant.name
+        );
+        assert!(
+            Path::new(SHADERS_DIR).join(&variant.source).is_file(),
+            "shader variant {:?} references missing source {:?}",
+            variant.name,
+            variant.source
+        );
+    }
+
+    // instrumented variants point at the variant they instrument, which has to be a plain one
+    for variant in &manifest.variant {
+        if let Some(kernel) = &variant.kernel {
+            let target = manifest.variant.iter().find(|other| &other.name == kernel);
+
+            assert!(
+                target.is_some_and(|target| target.kernel.is_none()),
+                "shader variant {:?} references unknown or instrumented kernel {:?}",
+                variant.name,
+                kernel
+            );
+        }
+    }
+
+    manifest
+}
+
+fn is_type_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// shaders without a manifest entry are compiled once with the default options
+fn default_variant(source: &str, file_name: &str) -> ShaderVariantDesc {
+    let name = file_name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect::<String>();
+
+    ShaderVariantDesc {
+        name,
+        source: String::from(source),
+        defines: BTreeMap::new(),
+        optimization: Optimization::default(),
+        debug_info: false,
+        kernel: None,
+    }
+}
+
+// headers that are not backed by a file, available through `#include <name>`
+fn generate_headers() -> BTreeMap<String, String> {
+    println!("cargo:rerun-if-changed=src/constants.rs");
+
+    let defines = [
+        ("WORKGROUP_SIZE", constants::WORKGROUP_SIZE.to_string()),
+        ("MAX_THREAD_TILE", constants::MAX_THREAD_TILE.to_string()),
+        (
+            "MAX_COOPERATIVE_TILES",
+            constants::MAX_COOPERATIVE_TILES.to_string(),
+        ),
+        (
+            "ROOFLINE_FMA_ITERATIONS",
+            constants::ROOFLINE_FMA_ITERATIONS.to_string(),
+        ),
+    ];
+
+    let mut header = String::new();
+    header.push_str("// generated by build.rs from src/constants.rs, do not edit\n\n");
+    header.push_str("#ifndef CONSTANTS_GLSL\n#define CONSTANTS_GLSL\n\n");
+    for (name, value) in defines {
+        header.push_str(&format!("#define {} {}\n", name, value));
+    }
+    header.push_str("\n#endif\n");
+
+    let mut headers = BTreeMap::new();
+    headers.insert(String::from("constants.glsl"), header);
+
+    headers
+}
+
+// `#include "..."` is resolved relative to the including file and `#include <...>` relative
+// to the shaders directory, falling back to the generated headers
+fn resolve_include(
+    requested_source: &str,
+    include_type: shaderc::IncludeType,
+    requesting_source: &str,
+    generated_headers: &BTreeMap<String, String>,
+) -> shaderc::IncludeCallbackResult {
+    let base_dir = match include_type {
+        shaderc::IncludeType::Relative => Path::new(requesting_source)
+            .parent()
+            .unwrap_or_else(|| Path::new(SHADERS_DIR)),
+        shaderc::IncludeType::Standard => Path::new(SHADERS_DIR),
+    };
+
+    let include_path = base_dir.join(requested_source);
+
+    if include_path.is_file() {
+        let content = fs::read_to_string(&include_path)
+            .map_err(|_| format!("failed to read include {:?}", include_path))?;
+
+        // included files are not visited by cargo on their own
+        println!("cargo:rerun-if-changed={}", include_path.display());
+
+        return Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_str().unwrap().replace('\\', "/"),
+            content,
+        });
+    }
+
+    if include_type == shaderc::IncludeType::Standard {
+        if let Some(content) = generated_headers.get(requested_source) {
+            return Ok(shaderc::ResolvedInclude {
+                resolved_name: format!("<generated>/{}", requested_source),
+                content: content.clone(),
+            });
+        }
+    }
+
+    Err(format!(
+        "failed to resolve include {:?} from {:?}",
+        requested_source, requesting_source
+    ))
+}
+
+fn compile_shader(
+    path_buf: &std::path::PathBuf,
+    shader_kind: shaderc::ShaderKind,
+    variant: &ShaderVariantDesc,
+    optimization: shaderc::OptimizationLevel,
+    generated_headers: &BTreeMap<String, String>,
+) -> shaderc::CompilationArtifact {
     let shader_str = fs::read_to_string(path_buf)
         .expect(&format!("failed to read shader {:?} to string", path_buf));
 
     let compiler = shaderc::Compiler::new().expect("failed to create shader compilier");
 
-    println!("compiling shader {:?}", path_buf);
-
     let mut compiler_options =
         shaderc::CompileOptions::new().expect("failed to create shader compiler options");
-    compiler_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compiler_options.set_optimization_level(optimization);
+    compiler_options.set_include_callback(
+        |requested_source, include_type, requesting_source, _| {
+            resolve_include(
+                requested_source,
+                include_type,
+                requesting_source,
+                generated_headers,
+            )
+        },
+    );
+
+    for (name, value) in &variant.defines {
+        compiler_options.add_macro_definition(name, Some(value));
+    }
+
+    // profiling builds keep debug info in every variant so tools can map back to the source
+    if variant.debug_info || std::env::var_os("CARGO_FEATURE_SHADER_DEBUG_INFO").is_some() {
+        compiler_options.set_generate_debug_info();
+    }
 
     let spv = compiler
         .compile_into_spirv(
@@ -61,40 +307,359 @@ fn compile_shader(path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKin
             "main",
             Some(&compiler_options),
         )
-        .expect(&format!("failed to compile shader {:?}", path_buf));
-
-    let mut file_name = path_buf
-        .file_name()
-        .expect("shader file should have a name")
-        .to_os_string();
+        .expect(&format!(
+            "failed to compile shader {:?} variant {}",
+            path_buf, variant.name
+        ));
 
     println!("cargo:rerun-if-changed={}", path_buf.display());
 
-    file_name.push(".spv");
+    spv
+}
 
-    let mut spv_path = path_buf
-        .parent()
-        .expect("failed to get shader file parent folder")
-        .join("..")
-        .join("..")
-        .join("shaders");
+fn write_shader(spv: &shaderc::CompilationArtifact, spv_file_name: &str) -> String {
+    let spv_dir = Path::new("shaders");
 
-    std::fs::create_dir_all(spv_path.clone()).expect(&format!(
+    std::fs::create_dir_all(spv_dir).expect(&format!(
         "failed to create directory for shader {:?}",
-        path_buf
+        spv_file_name
+    ));
+
+    let spv_path = spv_dir.join(spv_file_name);
+
+    fs::write(&spv_path, spv.as_binary_u8()).expect("failed to write shader binary");
+
+    spv_path.to_str().unwrap().replace('\\', "/")
+}
+
+fn write_variant_enum(variants: &[CompiledVariant]) {
+    let mut src = String::new();
+
+    src.push_str("// generated by build.rs from src/shaders/shaders.toml, do not edit\n\n");
+    src.push_str("#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]\n");
+    src.push_str("pub enum ShaderVariant {\n");
+    for variant in variants {
+        src.push_str(&format!("    {},\n", variant.name));
+    }
+    src.push_str("}\n\n");
+
+    src.push_str("impl ShaderVariant {\n");
+
+    src.push_str("    pub const ALL: &'static [ShaderVariant] = &[\n");
+    for variant in variants {
+        src.push_str(&format!("        ShaderVariant::{},\n", variant.name));
+    }
+    src.push_str("    ];\n\n");
+
+    src.push_str("    pub fn name(self) -> &'static str {\n        match self {\n");
+    for variant in variants {
+        src.push_str(&format!(
+            "            ShaderVariant::{0} => {0:?},\n",
+            variant.name
+        ));
+    }
+    src.push_str("        }\n    }\n\n");
+
+    src.push_str("    pub fn spv_path(self) -> &'static std::path::Path {\n        match self {\n");
+    for variant in variants {
+        src.push_str(&format!(
+            "            ShaderVariant::{} => std::path::Path::new({:?}),\n",
+            variant.name, variant.spv_path
+        ));
+    }
+    src.push_str("        }\n    }\n\n");
+
+    src.push_str(
+        "    // the variant whose kernel this one instruments, itself for plain variants\n",
+    );
+    src.push_str("    pub fn kernel(self) -> ShaderVariant {\n        match self {\n");
+    for variant in variants {
+        src.push_str(&format!(
+            "            ShaderVariant::{} => ShaderVariant::{},\n",
+            variant.name, variant.kernel
+        ));
+    }
+    src.push_str("        }\n    }\n\n");
+
+    src.push_str("    pub fn from_name(name: &str) -> Option<Self> {\n");
+    src.push_str("        Self::ALL\n            .iter()\n            .copied()\n");
+    src.push_str("            .find(|variant| variant.name().eq_ignore_ascii_case(name))\n");
+    src.push_str("    }\n");
+
+    src.push_str("}\n");
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&out_dir).join("shader_variants.rs"), src)
+        .expect("failed to write shader variants");
+}
+
+fn write_struct(s: &reflect::Struct, structs: &mut BTreeMap<String, String>, push_constants: bool) {
+    for member in &s.members {
+        let mut ty = &member.ty;
+        while let reflect::Type::Array(element, _) = ty {
+            ty = element;
+        }
+
+        if let reflect::Type::Struct(nested) = ty {
+            write_struct(nested, structs, false);
+        }
+    }
+
+    let mut src = String::new();
+
+    src.push_str("#[repr(C)]\n");
+    src.push_str("#[derive(Clone, Copy, Debug, PartialEq)]\n");
+    src.push_str("#[allow(non_snake_case)]\n");
+    src.push_str(&format!("pub struct {} {{\n", s.name));
+
+    // padding is spelled out so the Rust layout matches the shader offsets exactly
+    let mut offset = 0;
+    let mut pad_count = 0;
+    let mut push_padding = |src: &mut String, size: u32| {
+        if size > 0 {
+            src.push_str(&format!("    pub _pad{}: [u8; {}],\n", pad_count, size));
+            pad_count += 1;
+        }
+    };
+
+    for member in &s.members {
+        assert!(
+            member.offset >= offset && member.offset % member.ty.align() == 0,
+            "member {}.{} at offset {} cannot be represented with #[repr(C)]",
+            s.name,
+            member.name,
+            member.offset
+        );
+
+        push_padding(&mut src, member.offset - offset);
+
+        src.push_str(&format!(
+            "    pub {}: {},\n",
+            member.name,
+            member.ty.rust_name()
+        ));
+
+        offset = member.offset + member.ty.size();
+    }
+
+    push_padding(&mut src, s.size() - offset);
+
+    src.push_str("}\n\n");
+
+    src.push_str(&format!("impl Default for {} {{\n", s.name));
+    src.push_str("    fn default() -> Self {\n");
+    src.push_str(
+        "        // all members are plain numbers, zero is a valid value for each of them\n",
+    );
+    src.push_str("        unsafe { std::mem::zeroed() }\n");
+    src.push_str("    }\n}\n\n");
+
+    if push_constants {
+        src.push_str(&format!("impl {} {{\n", s.name));
+        src.push_str("    pub fn as_bytes(&self) -> &[u8] {\n");
+        src.push_str("        // repr(C) without implicit padding, every byte is initialized\n");
+        src.push_str("        unsafe {\n");
+        src.push_str("            std::slice::from_raw_parts(\n");
+        src.push_str("                (self as *const Self).cast::<u8>(),\n");
+        src.push_str("                std::mem::size_of::<Self>(),\n");
+        src.push_str("            )\n");
+        src.push_str("        }\n");
+        src.push_str("    }\n}\n\n");
+    }
+
+    src.push_str(&format!(
+        "const _: () = assert!(std::mem::size_of::<{}>() == {});\n\n",
+        s.name,
+        s.size()
     ));
 
-    spv_path.push(file_name);
+    // blocks shared between shaders through includes map to a single Rust type
+    match structs.get(&s.name) {
+        Some(existing) => assert!(
+            existing == &src,
+            "struct {} has different layouts in different shaders",
+            s.name
+        ),
+        None => {
+            structs.insert(s.name.clone(), src);
+        }
+    }
+}
+
+fn write_shader_interface(variants: &[CompiledVariant]) {
+    let mut structs = BTreeMap::new();
+
+    for variant in variants {
+        if let Some(push_constants) = &variant.interface.push_constants {
+            write_struct(push_constants, &mut structs, true);
+        }
+    }
 
-    fs::write(spv_path, spv.as_binary_u8()).expect("failed to write shader binary");
+    let mut src = String::new();
+
+    src.push_str("// generated by build.rs from the reflected shader variants, do not edit\n\n");
+
+    for struct_src in structs.values() {
+        src.push_str(struct_src);
+    }
+
+    src.push_str("impl crate::shader_variants::ShaderVariant {\n");
+
+    src.push_str("    pub fn bindings(self) -> &'static [ShaderBinding] {\n        match self {\n");
+    for variant in variants {
+        src.push_str(&format!(
+            "            crate::shader_variants::ShaderVariant::{} => &[\n",
+            variant.name
+        ));
+        for binding in &variant.interface.bindings {
+            src.push_str(&format!(
+                "                ShaderBinding {{\n                    set: {},\n                    binding: {},\n                    descriptor_type: ash::vk::DescriptorType::{},\n                    descriptor_count: {},\n                    name: {:?},\n                    read_only: {},\n                }},\n",
+                binding.set,
+                binding.binding,
+                binding.descriptor_type,
+                binding.descriptor_count,
+                binding.name,
+                binding.read_only
+            ));
+        }
+        src.push_str("            ],\n");
+    }
+    src.push_str("        }\n    }\n\n");
+
+    src.push_str("    pub fn push_constant_size(self) -> u32 {\n        match self {\n");
+    for variant in variants {
+        let size = variant
+            .interface
+            .push_constants
+            .as_ref()
+            .map(|push_constants| reflect::round_up(push_constants.size(), 4))
+            .unwrap_or(0);
+
+        src.push_str(&format!(
+            "            crate::shader_variants::ShaderVariant::{} => {},\n",
+            variant.name, size
+        ));
+    }
+    src.push_str("        }\n    }\n");
+
+    src.push_str("}\n");
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&out_dir).join("shader_interface.rs"), src)
+        .expect("failed to write shader interface");
 }
 
 fn main() -> Result<(), i32> {
-    let shaders_dir = Path::new("src/shaders");
+    let shaders_dir = Path::new(SHADERS_DIR);
+
+    // new shader files have to trigger a rebuild as well
+    println!("cargo:rerun-if-changed={}", shaders_dir.display());
+    println!("cargo:rerun-if-env-changed=CARGO_FEATURE_SHADER_DEBUG_INFO");
+
+    let manifest = read_manifest();
+    let generated_headers = generate_headers();
+
+    let mut compiled_variants = Vec::new();
+    let mut compiled_sources = std::collections::HashSet::new();
+
+    let mut compile = |path_buf: &std::path::PathBuf, shader_kind: shaderc::ShaderKind| {
+        let source = path_buf
+            .strip_prefix(shaders_dir)
+            .expect("shader should be located in the shaders directory")
+            .to_str()
+            .expect("shader path cannot be converted to &str")
+            .replace('\\', "/");
+
+        let file_name = path_buf
+            .file_name()
+            .expect("shader file should have a name")
+            .to_str()
+            .expect("shader file name cannot be converted to &str");
+
+        let mut variants = manifest
+            .variant
+            .iter()
+            .filter(|variant| variant.source == source)
+            .cloned()
+            .collect::<Vec<_>>();
 
-    if let Err(_) = visit_dirs(shaders_dir, &compile_shader) {
+        let has_manifest_entry = !variants.is_empty();
+        if !has_manifest_entry {
+            variants.push(default_variant(&source, file_name));
+        }
+
+        for variant in variants {
+            let spv_file_name = if has_manifest_entry {
+                format!("{}.{}.spv", file_name, variant.name.to_lowercase())
+            } else {
+                format!("{}.spv", file_name)
+            };
+
+            println!("compiling shader {:?} variant {}", path_buf, variant.name);
+
+            let spv = compile_shader(
+                path_buf,
+                shader_kind,
+                &variant,
+                variant.optimization.into(),
+                &generated_headers,
+            );
+
+            let spv_path = write_shader(&spv, &spv_file_name);
+
+            // optimized binaries lose their debug names, so reflect an unoptimized build
+            let reflection_spv = compile_shader(
+                path_buf,
+                shader_kind,
+                &variant,
+                shaderc::OptimizationLevel::Zero,
+                &generated_headers,
+            );
+
+            let interface = reflect::reflect(reflection_spv.as_binary()).unwrap_or_else(|msg| {
+                panic!(
+                    "failed to reflect shader {:?} variant {}: {}",
+                    path_buf, variant.name, msg
+                )
+            });
+
+            compiled_variants.push(CompiledVariant {
+                kernel: variant.kernel.unwrap_or(variant.name.clone()),
+                name: variant.name,
+                spv_path,
+                interface,
+            });
+        }
+
+        compiled_sources.insert(source);
+    };
+
+    if let Err(_) = visit_dirs(shaders_dir, &mut compile) {
         return Err(1);
     }
 
+    for variant in &manifest.variant {
+        assert!(
+            compiled_sources.contains(&variant.source),
+            "shader variant {:?} source {:?} is not a known shader kind",
+            variant.name,
+            variant.source
+        );
+    }
+
+    // keep the generated enum stable regardless of the directory iteration order
+    compiled_variants.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for pair in compiled_variants.windows(2) {
+        assert!(
+            pair[0].name != pair[1].name,
+            "shader variant {:?} is generated more than once",
+            pair[0].name
+        );
+    }
+
+    write_variant_enum(&compiled_variants);
+    write_shader_interface(&compiled_variants);
+
     Ok(())
 }
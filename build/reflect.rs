@@ -0,0 +1,444 @@
+// SPIR-V reflection of the compiled shader variants, used to generate the Rust side of the
+// shader interface (push constant structs and descriptor bindings).
+
+use std::collections::HashMap;
+
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Decoration, Op, StorageClass};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Scalar(&'static str, u32),
+    Vector(&'static str, u32, u32),
+    Array(Box<Type>, u32),
+    Struct(Struct),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Struct {
+    pub name: String,
+    pub members: Vec<Member>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub offset: u32,
+    pub ty: Type,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: &'static str,
+    pub descriptor_count: u32,
+    pub name: String,
+    pub read_only: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderInterface {
+    pub push_constants: Option<Struct>,
+    pub bindings: Vec<Binding>,
+}
+
+impl Type {
+    // size and alignment of the matching `#[repr(C)]` Rust type
+    pub fn size(&self) -> u32 {
+        match self {
+            Type::Scalar(_, size) => *size,
+            Type::Vector(_, size, count) => size * count,
+            Type::Array(element, length) => element.size() * length,
+            Type::Struct(s) => s.size(),
+        }
+    }
+
+    pub fn align(&self) -> u32 {
+        match self {
+            Type::Scalar(_, size) | Type::Vector(_, size, _) => *size,
+            Type::Array(element, _) => element.align(),
+            Type::Struct(s) => s.align(),
+        }
+    }
+
+    pub fn rust_name(&self) -> String {
+        match self {
+            Type::Scalar(name, _) => String::from(*name),
+            Type::Vector(name, _, count) => format!("[{}; {}]", name, count),
+            Type::Array(element, length) => format!("[{}; {}]", element.rust_name(), length),
+            Type::Struct(s) => s.name.clone(),
+        }
+    }
+}
+
+impl Struct {
+    pub fn align(&self) -> u32 {
+        self.members.iter().map(|m| m.ty.align()).max().unwrap_or(1)
+    }
+
+    pub fn size(&self) -> u32 {
+        let end = self
+            .members
+            .last()
+            .map(|m| m.offset + m.ty.size())
+            .unwrap_or(0);
+
+        round_up(end, self.align())
+    }
+}
+
+pub fn round_up(value: u32, alignment: u32) -> u32 {
+    value + (alignment - value % alignment) % alignment
+}
+
+type Decorations = Vec<(Decoration, Option<u32>)>;
+
+struct Reflector {
+    module: Module,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), Decorations>,
+    definitions: HashMap<u32, Instruction>,
+}
+
+pub fn reflect(spv: &[u32]) -> Result<ShaderInterface, String> {
+    let module = rspirv::dr::load_words(spv).map_err(|err| format!("invalid spirv: {}", err))?;
+
+    let mut reflector = Reflector {
+        module,
+        names: HashMap::new(),
+        member_names: HashMap::new(),
+        decorations: HashMap::new(),
+        member_decorations: HashMap::new(),
+        definitions: HashMap::new(),
+    };
+
+    reflector.collect();
+    reflector.interface()
+}
+
+fn id(operand: &Operand) -> u32 {
+    match operand {
+        Operand::IdRef(id) => *id,
+        _ => panic!("expected id operand, got {:?}", operand),
+    }
+}
+
+fn literal(operand: &Operand) -> u32 {
+    match operand {
+        Operand::LiteralInt32(value) => *value,
+        _ => panic!("expected literal operand, got {:?}", operand),
+    }
+}
+
+impl Reflector {
+    fn collect(&mut self) {
+        for inst in &self.module.debug_names {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (Op::Name, [target, Operand::LiteralString(name)]) => {
+                    self.names.insert(id(target), name.clone());
+                }
+                (Op::MemberName, [target, member, Operand::LiteralString(name)]) => {
+                    self.member_names
+                        .insert((id(target), literal(member)), name.clone());
+                }
+                _ => {}
+            }
+        }
+
+        for inst in &self.module.annotations {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (Op::Decorate, [target, Operand::Decoration(decoration), rest @ ..]) => {
+                    self.decorations
+                        .entry(id(target))
+                        .or_default()
+                        .push((*decoration, rest.first().map(literal)));
+                }
+                (
+                    Op::MemberDecorate,
+                    [target, member, Operand::Decoration(decoration), rest @ ..],
+                ) => {
+                    self.member_decorations
+                        .entry((id(target), literal(member)))
+                        .or_default()
+                        .push((*decoration, rest.first().map(literal)));
+                }
+                _ => {}
+            }
+        }
+
+        for inst in &self.module.types_global_values {
+            if let Some(result_id) = inst.result_id {
+                self.definitions.insert(result_id, inst.clone());
+            }
+        }
+    }
+
+    fn decoration(&self, target: u32, decoration: Decoration) -> Option<Option<u32>> {
+        self.decorations
+            .get(&target)?
+            .iter()
+            .find(|(d, _)| *d == decoration)
+            .map(|(_, value)| *value)
+    }
+
+    fn member_decoration(
+        &self,
+        target: u32,
+        member: u32,
+        decoration: Decoration,
+    ) -> Option<Option<u32>> {
+        self.member_decorations
+            .get(&(target, member))?
+            .iter()
+            .find(|(d, _)| *d == decoration)
+            .map(|(_, value)| *value)
+    }
+
+    fn definition(&self, id: u32) -> Result<&Instruction, String> {
+        self.definitions
+            .get(&id)
+            .ok_or(format!("undefined spirv id {}", id))
+    }
+
+    fn constant(&self, id: u32) -> Result<u32, String> {
+        let inst = self.definition(id)?;
+
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::Constant, [Operand::LiteralInt32(value)]) => Ok(*value),
+            (Op::SpecConstant, _) => Err(String::from(
+                "arrays sized by specialization constants cannot be reflected",
+            )),
+            _ => Err(format!("spirv id {} is not an integer constant", id)),
+        }
+    }
+
+    fn interface(&self) -> Result<ShaderInterface, String> {
+        let mut push_constants = None;
+        let mut bindings = Vec::new();
+
+        for inst in &self.module.types_global_values {
+            if inst.class.opcode != Op::Variable {
+                continue;
+            }
+
+            let variable = inst.result_id.unwrap();
+            let storage_class = match inst.operands.first() {
+                Some(Operand::StorageClass(storage_class)) => *storage_class,
+                _ => continue,
+            };
+
+            let pointee = self.pointee(inst.result_type.unwrap())?;
+
+            match storage_class {
+                StorageClass::PushConstant => {
+                    if push_constants.is_some() {
+                        return Err(String::from("only one push constant block is supported"));
+                    }
+
+                    push_constants = Some(self.block(pointee)?);
+                }
+                StorageClass::Uniform
+                | StorageClass::StorageBuffer
+                | StorageClass::UniformConstant => {
+                    bindings.push(self.binding(variable, storage_class, pointee)?);
+                }
+                _ => {}
+            }
+        }
+
+        bindings.sort_by_key(|b| (b.set, b.binding));
+
+        Ok(ShaderInterface {
+            push_constants,
+            bindings,
+        })
+    }
+
+    fn pointee(&self, pointer_type: u32) -> Result<u32, String> {
+        let inst = self.definition(pointer_type)?;
+
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::TypePointer, [_, pointee]) => Ok(id(pointee)),
+            _ => Err(format!("spirv id {} is not a pointer type", pointer_type)),
+        }
+    }
+
+    fn block(&self, struct_type: u32) -> Result<Struct, String> {
+        let inst = self.definition(struct_type)?;
+
+        if inst.class.opcode != Op::TypeStruct {
+            return Err(format!("spirv id {} is not a struct type", struct_type));
+        }
+
+        let name = self
+            .names
+            .get(&struct_type)
+            .filter(|name| !name.is_empty())
+            .ok_or(format!("struct {} has no name", struct_type))?
+            .clone();
+
+        let mut members = Vec::new();
+        for (ind, member_type) in inst.operands.iter().enumerate() {
+            let ind = ind as u32;
+
+            let member_name = self
+                .member_names
+                .get(&(struct_type, ind))
+                .ok_or(format!("member {} of {} has no name", ind, name))?
+                .clone();
+
+            let offset = self
+                .member_decoration(struct_type, ind, Decoration::Offset)
+                .flatten()
+                .ok_or(format!("member {} of {} has no offset", member_name, name))?;
+
+            let ty = self
+                .member_type(id(member_type))
+                .map_err(|msg| format!("{}.{}: {}", name, member_name, msg))?;
+
+            members.push(Member {
+                name: member_name,
+                offset,
+                ty,
+            });
+        }
+
+        Ok(Struct { name, members })
+    }
+
+    fn member_type(&self, type_id: u32) -> Result<Type, String> {
+        let inst = self.definition(type_id)?;
+
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::TypeInt, [width, signedness]) => {
+                let signed = literal(signedness) == 1;
+                let name = match (literal(width), signed) {
+                    (8, true) => "i8",
+                    (8, false) => "u8",
+                    (16, true) => "i16",
+                    (16, false) => "u16",
+                    (32, true) => "i32",
+                    (32, false) => "u32",
+                    (64, true) => "i64",
+                    (64, false) => "u64",
+                    (width, _) => return Err(format!("unsupported int width {}", width)),
+                };
+                Ok(Type::Scalar(name, literal(width) / 8))
+            }
+            (Op::TypeFloat, [width]) => match literal(width) {
+                // half floats are exposed as their raw bits
+                16 => Ok(Type::Scalar("u16", 2)),
+                32 => Ok(Type::Scalar("f32", 4)),
+                64 => Ok(Type::Scalar("f64", 8)),
+                width => Err(format!("unsupported float width {}", width)),
+            },
+            (Op::TypeVector, [component, count]) => match self.member_type(id(component))? {
+                Type::Scalar(name, size) => Ok(Type::Vector(name, size, literal(count))),
+                _ => Err(String::from("unsupported vector component type")),
+            },
+            (Op::TypeArray, [element, length]) => {
+                let element = self.member_type(id(element))?;
+                let stride = self
+                    .decoration(type_id, Decoration::ArrayStride)
+                    .flatten()
+                    .ok_or(String::from("array has no stride"))?;
+
+                if stride != element.size() {
+                    return Err(format!(
+                        "array stride {} does not match the element size {}, use std430 or pad the element",
+                        stride,
+                        element.size()
+                    ));
+                }
+
+                Ok(Type::Array(Box::new(element), self.constant(id(length))?))
+            }
+            (Op::TypeStruct, _) => Ok(Type::Struct(self.block(type_id)?)),
+            (opcode, _) => Err(format!("unsupported member type {:?}", opcode)),
+        }
+    }
+
+    fn binding(
+        &self,
+        variable: u32,
+        storage_class: StorageClass,
+        pointee: u32,
+    ) -> Result<Binding, String> {
+        let set = self
+            .decoration(variable, Decoration::DescriptorSet)
+            .flatten()
+            .ok_or(format!("resource {} has no descriptor set", variable))?;
+
+        let binding = self
+            .decoration(variable, Decoration::Binding)
+            .flatten()
+            .ok_or(format!("resource {} has no binding", variable))?;
+
+        // arrays of descriptors
+        let (resource_type, descriptor_count) = {
+            let inst = self.definition(pointee)?;
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (Op::TypeArray, [element, length]) => (id(element), self.constant(id(length))?),
+                _ => (pointee, 1),
+            }
+        };
+
+        let inst = self.definition(resource_type)?;
+
+        let descriptor_type = match (storage_class, inst.class.opcode) {
+            (StorageClass::StorageBuffer, _) => "STORAGE_BUFFER",
+            (StorageClass::Uniform, _)
+                if self
+                    .decoration(resource_type, Decoration::BufferBlock)
+                    .is_some() =>
+            {
+                "STORAGE_BUFFER"
+            }
+            (StorageClass::Uniform, _) => "UNIFORM_BUFFER",
+            (StorageClass::UniformConstant, Op::TypeSampler) => "SAMPLER",
+            (StorageClass::UniformConstant, Op::TypeSampledImage) => "COMBINED_IMAGE_SAMPLER",
+            (StorageClass::UniformConstant, Op::TypeAccelerationStructureKHR) => {
+                "ACCELERATION_STRUCTURE_KHR"
+            }
+            (StorageClass::UniformConstant, Op::TypeImage) => {
+                let dim = &inst.operands[1];
+                let sampled = literal(&inst.operands[5]);
+                match (dim, sampled) {
+                    (Operand::Dim(rspirv::spirv::Dim::DimBuffer), 2) => "STORAGE_TEXEL_BUFFER",
+                    (Operand::Dim(rspirv::spirv::Dim::DimBuffer), _) => "UNIFORM_TEXEL_BUFFER",
+                    (Operand::Dim(rspirv::spirv::Dim::DimSubpassData), _) => "INPUT_ATTACHMENT",
+                    (_, 2) => "STORAGE_IMAGE",
+                    _ => "SAMPLED_IMAGE",
+                }
+            }
+            (_, opcode) => return Err(format!("unsupported resource type {:?}", opcode)),
+        };
+
+        // buffers are named after their block, everything else after the variable
+        let name = match inst.class.opcode {
+            Op::TypeStruct => self.names.get(&resource_type),
+            _ => self.names.get(&variable),
+        }
+        .cloned()
+        .unwrap_or_default();
+
+        let read_only = self.decoration(variable, Decoration::NonWritable).is_some()
+            || (inst.class.opcode == Op::TypeStruct
+                && (0..inst.operands.len() as u32).all(|member| {
+                    self.member_decoration(resource_type, member, Decoration::NonWritable)
+                        .is_some()
+                }));
+
+        Ok(Binding {
+            set,
+            binding,
+            descriptor_type,
+            descriptor_count,
+            name,
+            read_only,
+        })
+    }
+}
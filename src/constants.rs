@@ -1,3 +1,10 @@
 pub const N: usize = 2048;
+// also exported to the shaders as a #define in the generated constants.glsl
 pub const WORKGROUP_SIZE: u32 = 16;
+// largest block of C computed by one invocation of the register blocked kernel, also exported to the shaders
+pub const MAX_THREAD_TILE: u32 = 8;
+// cooperative matrices accumulated by one subgroup of the cooperative matrix kernel, also exported to the shaders
+pub const MAX_COOPERATIVE_TILES: u32 = 4;
+// iterations of the FMA micro-benchmark used by the roofline report, also exported to the shaders
+pub const ROOFLINE_FMA_ITERATIONS: u32 = 256;
 pub const DATA_SIZE: usize = N * N * std::mem::size_of::<f32>();
@@ -1,10 +1,16 @@
 mod constants;
 mod matrix;
+mod shader_interface;
+mod shader_variants;
 mod vulkan;
 
 use matrix::Matrix;
 
-use vulkan::VulkanData;
+use shader_interface::PushConst;
+
+use shader_variants::ShaderVariant;
+
+use vulkan::{AlignedHostMemory, GpuMatrix, MatrixBuffers, TaskGraph, Validation, VulkanData};
 
 use rand::Rng;
 
@@ -26,6 +32,21 @@ fn main() {
 
     let _ = simplelog::CombinedLogger::init(loggers);
 
+    // `--device-report [json]` describes every physical device instead of running the kernels,
+    // nothing else is printed so the JSON can be piped
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if let Some(ind) = args.iter().position(|arg| arg == "--device-report") {
+        let json = args.get(ind + 1).is_some_and(|format| format == "json");
+
+        if let Err(msg) = print_device_reports(json) {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+
+        return;
+    }
+
     let mut a = Matrix::new(4);
     a.fill(1.0f32);
 
@@ -42,7 +63,20 @@ fn main() {
     ];
     let instance_extensions = vec![ash::extensions::ext::DebugUtils::name()];
 
-    let vulkan_data = match VulkanData::new(&instance_extensions, &device_extensions) {
+    let shader_variant = match get_shader_variant() {
+        Ok(variant) => variant,
+        Err(msg) => {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+    };
+
+    let mut vulkan_data = match VulkanData::new(
+        &instance_extensions,
+        &device_extensions,
+        shader_variant,
+        get_validation(),
+    ) {
         Ok(data) => data,
         Err(msg) => {
             log::error!("{}", msg);
@@ -50,10 +84,34 @@ fn main() {
         }
     };
 
+    // what is left for other jobs once the matrices are allocated
+    let memory_budget = vulkan_data.memory_budget();
+
+    print!("{}", memory_budget);
+    println!(
+        "room for {} more multiplications of this size",
+        memory_budget.concurrent_jobs(3 * constants::DATA_SIZE as u64)
+    );
+
+    if std::env::args().any(|arg| arg == "--autotune") {
+        if let Err(msg) = vulkan_data.autotune() {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+    }
+
+    // `--counters` collects hardware performance counters for every dispatch
+    if std::env::args().any(|arg| arg == "--counters") {
+        if let Err(msg) = vulkan_data.enable_performance_counters() {
+            log::warn!("{}, performance counters are not collected", msg);
+        }
+    }
+
     let mut rng = rand::thread_rng();
 
-    let mut a = vec![0.0f32; constants::N * constants::N];
-    let mut b = vec![0.0f32; constants::N * constants::N];
+    // aligned so the uploads can import the matrices instead of staging them
+    let mut a = AlignedHostMemory::<f32>::new(&vulkan_data, constants::N * constants::N);
+    let mut b = AlignedHostMemory::<f32>::new(&vulkan_data, constants::N * constants::N);
 
     type M = nalgebra::OMatrix<f32, nalgebra::Dynamic, nalgebra::Dynamic>;
     let mut mat_a = M::from_element(constants::N, constants::N, 0.0f32);
@@ -86,7 +144,19 @@ fn main() {
         let start = std::time::Instant::now();
 
         let result = match vulkan_data.multiply(&a, &b) {
-            Ok(data) => data,
+            Ok((data, report)) => {
+                for message in &report.printf_messages {
+                    println!("debug printf: {}", message);
+                }
+
+                if let Some(clock_profile) = &report.clock_profile {
+                    println!("workgroup clocks: {}", clock_profile);
+                }
+
+                print!("{}", report.statistics);
+
+                data
+            }
             Err(msg) => {
                 log::error!("{}", msg);
                 panic!("{}", msg);
@@ -101,16 +171,406 @@ fn main() {
             for col in 0..constants::N {
                 assert!(fuzzy_compare(
                     result[constants::N * row + col],
-                    mat_c[(row, col)]
+                    mat_c[(row, col)],
+                    get_epsilon(vulkan_data.shader_variant)
                 ));
             }
         }
     }
 
+    // `--chain` multiplies (A * B) * B without taking the intermediate product off the device
+    if args.iter().any(|arg| arg == "--chain") {
+        if let Err(msg) = check_chain(&vulkan_data, &a, &b) {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+    }
+
+    // `--replay <count>` records A * B once and submits it count times, with a new B each time
+    if let Some(ind) = args.iter().position(|arg| arg == "--replay") {
+        let result = match args.get(ind + 1).and_then(|n| n.parse::<usize>().ok()) {
+            Some(count) => check_replay(&vulkan_data, &a, &b, count),
+            None => Err(String::from("--replay requires a submission count")),
+        };
+
+        if let Err(msg) = result {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+    }
+
+    // `--out-of-core <n>` multiplies n x n matrices streamed through device memory in tiles
+    if let Some(ind) = args.iter().position(|arg| arg == "--out-of-core") {
+        let result = match args.get(ind + 1).and_then(|n| n.parse::<usize>().ok()) {
+            Some(n) => check_out_of_core(&vulkan_data, n),
+            None => Err(String::from("--out-of-core requires a matrix size")),
+        };
+
+        if let Err(msg) = result {
+            log::error!("{}", msg);
+            panic!("{}", msg);
+        }
+    }
+
+    // `--roofline <path>` prints the roofline report and writes its chart as an SVG
+    if let Some(ind) = args.iter().position(|arg| arg == "--roofline") {
+        let result = match args.get(ind + 1) {
+            Some(path) => vulkan::measure_roofline(&vulkan_data).and_then(|roofline| {
+                print!("{}", roofline);
+                roofline.write_svg(std::path::Path::new(path))
+            }),
+            None => Err(String::from("--roofline requires a file path")),
+        };
+
+        if let Err(msg) = result {
+            log::error!("{}", msg);
+        }
+    }
+
+    // `--trace <path>` writes the CPU and GPU scopes as a Chrome trace
+    if let Some(ind) = args.iter().position(|arg| arg == "--trace") {
+        let result = match args.get(ind + 1) {
+            Some(path) => vulkan_data
+                .profiler
+                .borrow()
+                .write_chrome_trace(std::path::Path::new(path)),
+            None => Err(String::from("--trace requires a file path")),
+        };
+
+        if let Err(msg) = result {
+            log::error!("{}", msg);
+        }
+    }
+
     vulkan_data.clean();
 }
 
-fn fuzzy_compare(a: f32, b: f32) -> bool {
-    const EPSILON: f32 = 0.01f32;
-    return (a - b).abs() < EPSILON;
+// the product both ways: one submission per multiplication and one task graph for the chain
+fn check_chain(vulkan_data: &VulkanData, a: &[f32], b: &[f32]) -> Result<(), String> {
+    let n = constants::N;
+
+    let gpu_a = GpuMatrix::upload(vulkan_data, n, n, a)?;
+    let gpu_b = GpuMatrix::upload(vulkan_data, n, n, b)?;
+
+    let start = std::time::Instant::now();
+
+    let steps_result = chain_steps(vulkan_data, &gpu_a, &gpu_b);
+
+    println!("chained time {} ms", start.elapsed().as_millis());
+
+    let graph_result = chain_graph(vulkan_data, &gpu_a, &gpu_b);
+
+    gpu_a.clean(vulkan_data);
+    gpu_b.clean(vulkan_data);
+
+    let mat_a = nalgebra::DMatrix::from_row_slice(n, n, a);
+    let mat_b = nalgebra::DMatrix::from_row_slice(n, n, b);
+    let expected = &mat_a * &mat_b * &mat_b;
+
+    // the entries grow with n, the error with them
+    let epsilon = get_epsilon(vulkan_data.shader_variant);
+
+    for (name, result) in [("chained", steps_result?), ("task graph", graph_result?)] {
+        for row in 0..n {
+            for col in 0..n {
+                let expected = expected[(row, col)];
+
+                if !fuzzy_compare(
+                    result[row * n + col],
+                    expected,
+                    epsilon * expected.abs().max(1.0),
+                ) {
+                    return Err(format!(
+                        "{} result at ({}, {}) is {}, expected {}",
+                        name,
+                        row,
+                        col,
+                        result[row * n + col],
+                        expected
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn chain_steps(
+    vulkan_data: &VulkanData,
+    gpu_a: &GpuMatrix<f32>,
+    gpu_b: &GpuMatrix<f32>,
+) -> Result<Vec<f32>, String> {
+    let (gpu_ab, _) = gpu_a.multiply(vulkan_data, gpu_b)?;
+
+    let result = gpu_ab
+        .multiply(vulkan_data, gpu_b)
+        .and_then(|(gpu_abb, _)| {
+            let data = gpu_abb.download(vulkan_data);
+            gpu_abb.clean(vulkan_data);
+            data
+        });
+
+    gpu_ab.clean(vulkan_data);
+
+    result
+}
+
+// B is copied on the device first, so the graph orders a copy, a multiplication reading the
+// copy and one reading the product
+fn chain_graph(
+    vulkan_data: &VulkanData,
+    gpu_a: &GpuMatrix<f32>,
+    gpu_b: &GpuMatrix<f32>,
+) -> Result<Vec<f32>, String> {
+    let n = constants::N;
+
+    let mut matrices = Vec::new();
+
+    let result = (0..3)
+        .try_for_each(|_| {
+            matrices.push(GpuMatrix::<f32>::new(vulkan_data, n, n)?);
+            Ok::<_, String>(())
+        })
+        .and_then(|_| {
+            let [gpu_b_copy, gpu_ab, gpu_abb] = [&matrices[0], &matrices[1], &matrices[2]];
+
+            let report = TaskGraph::new()
+                .copy(&gpu_b.mem_buffer, &gpu_b_copy.mem_buffer)
+                .multiply(MatrixBuffers {
+                    a: &gpu_a.mem_buffer,
+                    b: &gpu_b_copy.mem_buffer,
+                    c: &gpu_ab.mem_buffer,
+                })
+                .multiply(MatrixBuffers {
+                    a: &gpu_ab.mem_buffer,
+                    b: &gpu_b_copy.mem_buffer,
+                    c: &gpu_abb.mem_buffer,
+                })
+                .execute(vulkan_data)?;
+
+            println!("task graph: {}", report);
+
+            gpu_abb.download(vulkan_data)
+        });
+
+    for matrix in matrices {
+        matrix.clean(vulkan_data);
+    }
+
+    result
+}
+
+// B is scaled by the submission index, only the last product is compared with the host
+fn check_replay(
+    vulkan_data: &VulkanData,
+    a: &[f32],
+    b: &[f32],
+    count: usize,
+) -> Result<(), String> {
+    let n = constants::N;
+
+    let mut matrices = Vec::new();
+
+    let result = (0..3)
+        .try_for_each(|_| {
+            matrices.push(GpuMatrix::<f32>::new(vulkan_data, n, n)?);
+            Ok::<_, String>(())
+        })
+        .and_then(|_| {
+            let [gpu_a, gpu_b, gpu_c] = [&matrices[0], &matrices[1], &matrices[2]];
+
+            gpu_a.write(vulkan_data, a)?;
+
+            let mut sequence = TaskGraph::new()
+                .multiply(MatrixBuffers {
+                    a: &gpu_a.mem_buffer,
+                    b: &gpu_b.mem_buffer,
+                    c: &gpu_c.mem_buffer,
+                })
+                .record(vulkan_data)?;
+
+            let start = std::time::Instant::now();
+
+            let mut scaled_b = b.to_vec();
+            let mut stalls = 0;
+
+            let replay_result = (0..count).try_for_each(|i| {
+                scaled_b
+                    .iter_mut()
+                    .zip(b)
+                    .for_each(|(scaled, b)| *scaled = b * (i + 1) as f32);
+
+                // the previous submission has to finish before B changes
+                if sequence.is_in_flight()? {
+                    stalls += 1;
+                    sequence.wait()?;
+                }
+
+                gpu_b.write(vulkan_data, &scaled_b)?;
+
+                // recording the sequence again halfway must not change the result
+                if i == count / 2 {
+                    sequence.set_push_constants(PushConst { N: n as u32 })?;
+                }
+
+                sequence.submit()
+            });
+
+            let wait_result = sequence.wait();
+
+            println!(
+                "replayed {} tasks with {} barriers {} times in {} ms, {} waits for the previous submission",
+                sequence.task_count,
+                sequence.barrier_count,
+                count,
+                start.elapsed().as_millis(),
+                stalls
+            );
+
+            sequence.clean();
+
+            replay_result.and(wait_result)?;
+
+            gpu_c.download(vulkan_data)
+        });
+
+    for matrix in matrices {
+        matrix.clean(vulkan_data);
+    }
+
+    if count == 0 {
+        return result.map(|_| ());
+    }
+
+    let result = result?;
+
+    let mat_a = nalgebra::DMatrix::from_row_slice(n, n, a);
+    let mat_b = nalgebra::DMatrix::from_row_slice(n, n, b) * count as f32;
+    let expected = &mat_a * &mat_b;
+
+    let epsilon = get_epsilon(vulkan_data.shader_variant);
+
+    for row in 0..n {
+        for col in 0..n {
+            let expected = expected[(row, col)];
+
+            if !fuzzy_compare(
+                result[row * n + col],
+                expected,
+                epsilon * expected.abs().max(1.0),
+            ) {
+                return Err(format!(
+                    "replayed result at ({}, {}) is {}, expected {}",
+                    row,
+                    col,
+                    result[row * n + col],
+                    expected
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// compares a sample of the elements with the host, the full product is too slow for large n
+fn check_out_of_core(vulkan_data: &VulkanData, n: usize) -> Result<(), String> {
+    let mut rng = rand::thread_rng();
+
+    let a = (0..n * n)
+        .map(|_| rng.gen_range(0.0f32..1.0f32))
+        .collect::<Vec<_>>();
+    let b = (0..n * n)
+        .map(|_| rng.gen_range(0.0f32..1.0f32))
+        .collect::<Vec<_>>();
+
+    let start = std::time::Instant::now();
+
+    let c = vulkan::multiply_out_of_core(vulkan_data, &a, &b, n)?;
+
+    println!("out-of-core time {} ms", start.elapsed().as_millis());
+
+    for _ in 0..64 {
+        let row = rng.gen_range(0..n);
+        let col = rng.gen_range(0..n);
+
+        let expected = (0..n).map(|k| a[row * n + k] * b[k * n + col]).sum::<f32>();
+
+        // the sums run in a different order, the error grows with n
+        if !fuzzy_compare(c[row * n + col], expected, 1e-5 * n as f32) {
+            return Err(format!(
+                "out-of-core result at ({}, {}) is {}, expected {}",
+                row,
+                col,
+                c[row * n + col],
+                expected
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn print_device_reports(json: bool) -> Result<(), String> {
+    let reports = vulkan::get_device_reports()?;
+
+    if json {
+        let reports_str = serde_json::to_string_pretty(&reports)
+            .map_err(|_| String::from("failed to serialize device reports"))?;
+
+        println!("{}", reports_str);
+    } else {
+        for report in &reports {
+            println!("{}", report);
+        }
+    }
+
+    Ok(())
+}
+
+// the kernel is picked with `--kernel <name>`, the tiled one is used by default
+fn get_shader_variant() -> Result<ShaderVariant, String> {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    let name = match args.iter().position(|arg| arg == "--kernel") {
+        Some(ind) => args
+            .get(ind + 1)
+            .ok_or(String::from("--kernel requires a shader variant name"))?,
+        None => return Ok(ShaderVariant::Tiled),
+    };
+
+    ShaderVariant::from_name(name).ok_or(format!(
+        "unknown shader variant {}, available variants: {:?}",
+        name,
+        ShaderVariant::ALL
+    ))
+}
+
+// `--validation` logs the validation messages, `--validation-fatal` also fails on errors and
+// `--debug-printf` captures the kernels' debugPrintfEXT output
+fn get_validation() -> Validation {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if args.iter().any(|arg| arg == "--debug-printf") {
+        Validation::DebugPrintf
+    } else if args.iter().any(|arg| arg == "--validation-fatal") {
+        Validation::FailOnError
+    } else if args.iter().any(|arg| arg == "--validation") {
+        Validation::Enabled
+    } else {
+        Validation::Disabled
+    }
+}
+
+// the cooperative matrix kernel rounds its inputs to f16
+fn get_epsilon(shader_variant: ShaderVariant) -> f32 {
+    match shader_variant {
+        ShaderVariant::Cooperative => 0.1f32,
+        _ => 0.01f32,
+    }
+}
+
+fn fuzzy_compare(a: f32, b: f32, epsilon: f32) -> bool {
+    return (a - b).abs() < epsilon;
 }
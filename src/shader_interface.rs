@@ -0,0 +1,14 @@
+use ash::vk;
+
+// a descriptor binding of a shader variant, reflected from its SPIR-V by build.rs
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub descriptor_count: u32,
+    pub name: &'static str,
+    pub read_only: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/shader_interface.rs"));
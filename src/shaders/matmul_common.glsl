@@ -0,0 +1,28 @@
+#ifndef MATMUL_COMMON_GLSL
+#define MATMUL_COMMON_GLSL
+
+// interface shared by the matrix multiplication kernels: C = A * B for N x N row major matrices
+
+// kernels with vectorized loads define this as vec4 before including the file
+#ifndef MATMUL_ELEMENT
+#define MATMUL_ELEMENT float
+#endif
+
+layout(push_constant) uniform PushConst
+{
+    uint N;
+};
+
+layout (set = 0, binding = 0) readonly buffer A {
+    MATMUL_ELEMENT a[];
+};
+
+layout (set = 0, binding = 1) readonly buffer B {
+    MATMUL_ELEMENT b[];
+};
+
+layout (set = 0, binding = 2) buffer C {
+    MATMUL_ELEMENT c[];
+};
+
+#endif
@@ -0,0 +1,67 @@
+#ifndef MATMUL_PROFILE_GLSL
+#define MATMUL_PROFILE_GLSL
+
+// per-workgroup clocks, compiled in by the variants defining PROFILE_CLOCKS; kernels call
+// profileBegin() first and profileEnd() last, both from uniform control flow. Included before
+// anything else so the extensions come first.
+
+#ifdef PROFILE_CLOCKS
+
+#extension GL_EXT_shader_realtime_clock : require
+
+#ifdef PROFILE_SM_ID
+#extension GL_NV_shader_sm_builtins : require
+#endif
+
+struct WorkgroupClock
+{
+    // clockRealtime2x32EXT, low word first
+    uvec2 start;
+    uvec2 end;
+    // gl_SMIDNV with PROFILE_SM_ID, ~0 otherwise
+    uint smId;
+    // the host clears the buffer, workgroups that ran set this to 1
+    uint recorded;
+};
+
+layout (set = 0, binding = 3) writeonly buffer WorkgroupClocks {
+    WorkgroupClock workgroupClocks[];
+};
+
+uvec2 profileStartClock;
+
+void profileBegin()
+{
+    profileStartClock = clockRealtime2x32EXT();
+}
+
+void profileEnd()
+{
+    // the workgroup is done once its slowest invocation is
+    barrier();
+
+    if (gl_LocalInvocationIndex == 0)
+    {
+        const uint workgroup = gl_WorkGroupID.x
+            + gl_NumWorkGroups.x * (gl_WorkGroupID.y + gl_NumWorkGroups.y * gl_WorkGroupID.z);
+
+        workgroupClocks[workgroup].start = profileStartClock;
+        workgroupClocks[workgroup].end = clockRealtime2x32EXT();
+#ifdef PROFILE_SM_ID
+        workgroupClocks[workgroup].smId = gl_SMIDNV;
+#else
+        workgroupClocks[workgroup].smId = ~0u;
+#endif
+        workgroupClocks[workgroup].recorded = 1;
+    }
+}
+
+#else
+
+void profileBegin() {}
+
+void profileEnd() {}
+
+#endif
+
+#endif
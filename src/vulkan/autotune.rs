@@ -0,0 +1,229 @@
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+use crate::shader_variants::ShaderVariant;
+
+use super::{DeviceCapabilities, KernelConfig, VulkanData};
+
+const AUTOTUNE_CACHE_PATH: &str = "autotune.json";
+
+const WARMUP_RUNS: usize = 2;
+const TIMED_RUNS: usize = 5;
+
+#[derive(Serialize, Deserialize, Default)]
+struct AutotuneCache {
+    entries: Vec<AutotuneEntry>,
+}
+
+// the best configuration found for a device, driver, kernel and problem size
+#[derive(Serialize, Deserialize)]
+struct AutotuneEntry {
+    device_name: String,
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    shader_variant: String,
+    n: usize,
+    config: KernelConfig,
+    gpu_time_ms: f32,
+}
+
+impl AutotuneEntry {
+    fn matches(
+        &self,
+        properties: &vk::PhysicalDeviceProperties,
+        shader_variant: ShaderVariant,
+        n: usize,
+    ) -> bool {
+        self.vendor_id == properties.vendor_id
+            && self.device_id == properties.device_id
+            && self.driver_version == properties.driver_version
+            && self.device_name == device_name(properties)
+            && self.shader_variant == shader_variant.name()
+            && self.n == n
+    }
+}
+
+fn device_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn read_cache() -> AutotuneCache {
+    std::fs::read_to_string(AUTOTUNE_CACHE_PATH)
+        .ok()
+        .and_then(|cache_str| match serde_json::from_str(&cache_str) {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                log::warn!(
+                    "ignoring invalid autotune cache {}: {}",
+                    AUTOTUNE_CACHE_PATH,
+                    err
+                );
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+fn write_cache(cache: &AutotuneCache) -> Result<(), String> {
+    let cache_str = serde_json::to_string_pretty(cache)
+        .map_err(|_| String::from("failed to serialize autotune cache"))?;
+
+    std::fs::write(AUTOTUNE_CACHE_PATH, cache_str)
+        .map_err(|_| format!("failed to write autotune cache {}", AUTOTUNE_CACHE_PATH))
+}
+
+// the cache is user editable and may come from another setup, so the config is checked again
+pub fn load_tuned_config(
+    properties: &vk::PhysicalDeviceProperties,
+    capabilities: &DeviceCapabilities,
+    shader_variant: ShaderVariant,
+    n: usize,
+) -> Option<KernelConfig> {
+    let config = read_cache()
+        .entries
+        .into_iter()
+        .find(|entry| entry.matches(properties, shader_variant, n))?
+        .config;
+
+    match config.check_limits(shader_variant, capabilities, n) {
+        Ok(()) => Some(config),
+        Err(msg) => {
+            log::warn!(
+                "ignoring autotuned kernel configuration {:?}: {}",
+                config,
+                msg
+            );
+            None
+        }
+    }
+}
+
+// fastest GPU time in ms of a kernel compatible with the pipeline layout of vulkan_data
+pub fn measure_gpu_time(
+    vulkan_data: &VulkanData,
+    shader_variant: ShaderVariant,
+    kernel_config: &KernelConfig,
+) -> Result<f32, String> {
+    let shader_module =
+        super::create_shader_module(&vulkan_data.device, shader_variant.spv_path())?;
+
+    let pipeline = super::create_pipeline(
+        &vulkan_data.device,
+        shader_module,
+        vulkan_data.pipeline_layout,
+        shader_variant,
+        kernel_config,
+    );
+
+    unsafe {
+        vulkan_data
+            .device
+            .destroy_shader_module(shader_module, None);
+    }
+
+    let pipeline = pipeline?;
+
+    let mut times = Vec::with_capacity(TIMED_RUNS);
+    let mut result = Ok(());
+
+    for run in 0..WARMUP_RUNS + TIMED_RUNS {
+        match super::dispatch(
+            vulkan_data,
+            shader_variant,
+            pipeline,
+            kernel_config,
+            vulkan_data.matrix_buffers(),
+        ) {
+            Ok(report) if run >= WARMUP_RUNS => times.push(report.gpu_time),
+            Ok(_) => {}
+            Err(msg) => {
+                result = Err(msg);
+                break;
+            }
+        }
+    }
+
+    unsafe {
+        vulkan_data.device.destroy_pipeline(pipeline, None);
+    }
+
+    result?;
+
+    // the fastest run is the least disturbed by other work on the GPU
+    Ok(times.into_iter().fold(f32::INFINITY, f32::min))
+}
+
+// times every candidate that fits the device limits on the current buffers and remembers the best
+pub fn autotune(vulkan_data: &VulkanData) -> Result<KernelConfig, String> {
+    log::info!(
+        "autotuning shader variant {} for n = {}",
+        vulkan_data.shader_variant.name(),
+        constants::N
+    );
+
+    if !vulkan_data.profiler.borrow().timestamps_supported() {
+        return Err(String::from(
+            "autotuning needs timestamps on the compute queue",
+        ));
+    }
+
+    let capabilities = &vulkan_data.device_capabilities;
+
+    let mut best: Option<(KernelConfig, f32)> = None;
+
+    for kernel_config in KernelConfig::candidates(vulkan_data.shader_variant, capabilities) {
+        if let Err(msg) =
+            kernel_config.check_limits(vulkan_data.shader_variant, capabilities, constants::N)
+        {
+            log::info!("skipping {:?}: {}", kernel_config, msg);
+            continue;
+        }
+
+        let time = match measure_gpu_time(vulkan_data, vulkan_data.shader_variant, &kernel_config) {
+            Ok(time) => time,
+            Err(msg) => {
+                log::warn!("skipping {:?}: {}", kernel_config, msg);
+                continue;
+            }
+        };
+
+        log::info!("{:?}: {} ms", kernel_config, time);
+
+        if best.is_none_or(|(_, best_time)| time < best_time) {
+            best = Some((kernel_config, time));
+        }
+    }
+
+    let (kernel_config, gpu_time_ms) =
+        best.ok_or(String::from("no autotune candidate fits the device limits"))?;
+
+    log::info!("best configuration {:?}: {} ms", kernel_config, gpu_time_ms);
+
+    let properties = &vulkan_data.physical_device_properties;
+
+    // instrumented variants share the configuration of the kernel they instrument
+    let kernel = vulkan_data.shader_variant.kernel();
+
+    let mut cache = read_cache();
+    cache
+        .entries
+        .retain(|entry| !entry.matches(properties, kernel, constants::N));
+    cache.entries.push(AutotuneEntry {
+        device_name: device_name(properties),
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        driver_version: properties.driver_version,
+        shader_variant: String::from(kernel.name()),
+        n: constants::N,
+        config: kernel_config,
+        gpu_time_ms,
+    });
+
+    write_cache(&cache)?;
+
+    Ok(kernel_config)
+}
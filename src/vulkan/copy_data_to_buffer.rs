@@ -1,55 +1,52 @@
 use crate::vulkan::MemBuffer;
 use ash::vk;
 
-use super::VulkanData;
+use super::{HostAccess, Pod, VulkanData};
 
-pub fn copy_data_to_buffer(
+// writes `data` at `offset` bytes into the buffer
+pub fn copy_data_to_buffer<T: Pod>(
     vulkan_data: &VulkanData,
     mem_buffer: &MemBuffer,
-    data: &[f32],
+    offset: vk::DeviceSize,
+    data: &[T],
 ) -> Result<(), String> {
-    let size = (data.len() * std::mem::size_of::<f32>()) as vk::DeviceSize;
-    assert!(size <= mem_buffer.size);
+    let _scope = vulkan_data.cpu_scope("upload");
 
-    // create staging buffer
-    let staging_mem_buffer = super::create_mem_buffer(
-        &vulkan_data.instance,
-        vulkan_data.physical_device,
-        &vulkan_data.device,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-    )?;
+    let size = std::mem::size_of_val(data) as vk::DeviceSize;
+    super::check_transfer_range::<T>(offset, size, mem_buffer.size)?;
 
-    // copy data to staging buffer
-    let mapped_data_ptr = unsafe {
-        vulkan_data
-            .device
-            .map_memory(
-                staging_mem_buffer.device_memory,
-                0,
-                size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .map_err(|_| String::from("failed to map buffer memory"))?
-    };
-
-    let mut data_slice = unsafe {
-        ash::util::Align::new(
-            mapped_data_ptr,
-            std::mem::align_of::<f32>() as vk::DeviceSize,
-            size,
-        )
-    };
-
-    data_slice.copy_from_slice(data);
+    if size == 0 {
+        return Ok(());
+    }
 
-    unsafe {
-        vulkan_data
-            .device
-            .unmap_memory(staging_mem_buffer.device_memory);
+    // once flushed, the next submission makes the host writes visible to the device
+    if mem_buffer.host_mapped() {
+        return write_mapped(vulkan_data, mem_buffer, offset, data);
     }
 
+    // the caller's memory is the transfer source when it can be imported, which saves copying
+    // it into a staging buffer
+    let staging_mem_buffer =
+        match super::import_host_memory(vulkan_data, data, vk::BufferUsageFlags::TRANSFER_SRC) {
+            Ok(imported_mem_buffer) => imported_mem_buffer,
+            Err(msg) => {
+                log::info!("uploading through a staging buffer: {}", msg);
+
+                // create staging buffer
+                let staging_mem_buffer = super::create_host_mem_buffer(
+                    vulkan_data,
+                    size,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    HostAccess::Upload,
+                )?;
+
+                // copy data to staging buffer
+                write_mapped(vulkan_data, &staging_mem_buffer, 0, data)?;
+
+                staging_mem_buffer
+            }
+        };
+
     // allocate command buffer
     let command_buffer = super::allocate_command_buffer(vulkan_data)?;
 
@@ -57,9 +54,18 @@ pub fn copy_data_to_buffer(
     super::begin_command_buffer(vulkan_data, command_buffer)?;
 
     // copy data to device local buffer
-    let buffer_copy = vk::BufferCopy::builder().size(size).build();
+    let buffer_copy = vk::BufferCopy::builder()
+        .dst_offset(offset)
+        .size(size)
+        .build();
 
-    unsafe {
+    let scope_id = vulkan_data.profiler.borrow_mut().begin_gpu_scope(
+        &vulkan_data.device,
+        command_buffer,
+        "upload",
+    )?;
+
+    let ended = unsafe {
         vulkan_data.device.cmd_copy_buffer(
             command_buffer,
             staging_mem_buffer.buffer,
@@ -67,14 +73,27 @@ pub fn copy_data_to_buffer(
             &[buffer_copy],
         );
 
+        vulkan_data.profiler.borrow_mut().end_gpu_scope(
+            &vulkan_data.device,
+            command_buffer,
+            scope_id,
+        );
+
         vulkan_data
             .device
             .end_command_buffer(command_buffer)
-            .map_err(|_| String::from("failed to end command buffer"))?
-    }
+            .map_err(|_| String::from("failed to end command buffer"))
+    };
 
     // submit
-    super::submit(vulkan_data, command_buffer)?;
+    let submission = ended.and_then(|()| super::submit(vulkan_data, command_buffer));
+
+    // the scopes of a command buffer that never ran cannot be resolved
+    if submission.is_err() {
+        vulkan_data.profiler.borrow_mut().discard_gpu_scopes();
+    }
+
+    let submission = submission?;
 
     // wait
     unsafe {
@@ -85,6 +104,11 @@ pub fn copy_data_to_buffer(
             .map_err(|_| String::from("failed to wait device idle"))?;
     }
 
+    vulkan_data
+        .profiler
+        .borrow_mut()
+        .resolve_gpu_scopes(&vulkan_data.device, &submission)?;
+
     // clean
     unsafe {
         // destroy buffer
@@ -114,3 +138,30 @@ pub fn copy_data_to_buffer(
 
     Ok(())
 }
+
+fn write_mapped<T: Pod>(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+    offset: vk::DeviceSize,
+    data: &[T],
+) -> Result<(), String> {
+    let mapped_data_ptr = super::map_mem_buffer(vulkan_data, mem_buffer)?;
+
+    let bytes = super::pod_bytes(data);
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            mapped_data_ptr.cast::<u8>().add(offset as usize),
+            bytes.len(),
+        );
+    }
+
+    let flushed = super::flush_mem_buffer(vulkan_data, mem_buffer);
+
+    unsafe {
+        vulkan_data.device.unmap_memory(mem_buffer.device_memory);
+    }
+
+    flushed
+}
@@ -1,16 +1,35 @@
 use ash::vk;
 
-pub fn create_descriptor_pool(device: &ash::Device) -> Result<vk::DescriptorPool, String> {
+use crate::shader_interface::ShaderBinding;
+
+// room for `max_sets` sets of the bindings
+pub fn create_descriptor_pool(
+    device: &ash::Device,
+    shader_bindings: &[ShaderBinding],
+    max_sets: u32,
+    flags: vk::DescriptorPoolCreateFlags,
+) -> Result<vk::DescriptorPool, String> {
     log::info!("creating descriptor pool");
 
-    let pool_size = vk::DescriptorPoolSize::builder()
-        .ty(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(3)
-        .build();
+    let mut sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+    for shader_binding in shader_bindings {
+        match sizes
+            .iter_mut()
+            .find(|size| size.ty == shader_binding.descriptor_type)
+        {
+            Some(size) => size.descriptor_count += shader_binding.descriptor_count * max_sets,
+            None => sizes.push(
+                vk::DescriptorPoolSize::builder()
+                    .ty(shader_binding.descriptor_type)
+                    .descriptor_count(shader_binding.descriptor_count * max_sets)
+                    .build(),
+            ),
+        }
+    }
 
-    let sizes = [pool_size];
     let create_info = vk::DescriptorPoolCreateInfo::builder()
-        .max_sets(1)
+        .flags(flags)
+        .max_sets(max_sets)
         .pool_sizes(&sizes)
         .build();
 
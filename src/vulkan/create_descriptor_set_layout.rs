@@ -1,30 +1,23 @@
 use ash::vk;
 
+use crate::shader_interface::ShaderBinding;
+
 pub fn create_descriptor_set_layout(
     device: &ash::Device,
+    shader_bindings: &[ShaderBinding],
 ) -> Result<vk::DescriptorSetLayout, String> {
-    let binding_a = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::COMPUTE)
-        .build();
-
-    let binding_b = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::COMPUTE)
-        .build();
-
-    let binding_c = vk::DescriptorSetLayoutBinding::builder()
-        .binding(2)
-        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::COMPUTE)
-        .build();
+    let bindings = shader_bindings
+        .iter()
+        .map(|shader_binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(shader_binding.binding)
+                .descriptor_type(shader_binding.descriptor_type)
+                .descriptor_count(shader_binding.descriptor_count)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        })
+        .collect::<Vec<_>>();
 
-    let bindings = [binding_a, binding_b, binding_c];
     let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
         .bindings(&bindings)
         .build();
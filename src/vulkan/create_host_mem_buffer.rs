@@ -0,0 +1,64 @@
+use ash::vk;
+
+use super::{MemBuffer, VulkanData};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostAccess {
+    // written by the host, read by the device
+    Upload,
+    // written by the device, read by the host
+    Readback,
+}
+
+impl HostAccess {
+    // best first; non-coherent memory is flushed and invalidated by the mapping functions
+    fn memory_flag_candidates(&self) -> &'static [vk::MemoryPropertyFlags] {
+        const HOST_VISIBLE: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+        const HOST_COHERENT: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+            HOST_VISIBLE.as_raw() | vk::MemoryPropertyFlags::HOST_COHERENT.as_raw(),
+        );
+        const HOST_CACHED: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+            HOST_VISIBLE.as_raw() | vk::MemoryPropertyFlags::HOST_CACHED.as_raw(),
+        );
+        const HOST_CACHED_COHERENT: vk::MemoryPropertyFlags =
+            vk::MemoryPropertyFlags::from_raw(HOST_COHERENT.as_raw() | HOST_CACHED.as_raw());
+
+        match self {
+            // sequential writes are fine in write combined memory
+            HostAccess::Upload => &[HOST_COHERENT, HOST_VISIBLE],
+            // uncached reads are slow
+            HostAccess::Readback => &[
+                HOST_CACHED_COHERENT,
+                HOST_CACHED,
+                HOST_COHERENT,
+                HOST_VISIBLE,
+            ],
+        }
+    }
+}
+
+// a staging buffer in the best host visible memory type for the access
+pub fn create_host_mem_buffer(
+    vulkan_data: &VulkanData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    host_access: HostAccess,
+) -> Result<MemBuffer, String> {
+    let mem_buffer = super::create_mem_buffer_preferring(
+        &vulkan_data.instance,
+        vulkan_data.physical_device,
+        &vulkan_data.device,
+        size,
+        usage,
+        host_access.memory_flag_candidates(),
+        vulkan_data.device_capabilities.memory_budget,
+    )?;
+
+    log::info!(
+        "{:?} buffer memory flags: {:#x}",
+        host_access,
+        mem_buffer.memory_flags.as_raw()
+    );
+
+    Ok(mem_buffer)
+}
@@ -3,6 +3,8 @@ use ash::vk;
 pub fn create_instance<'a>(
     entry: &ash::Entry,
     instance_extensions: &Vec<&'a std::ffi::CStr>,
+    layers: &Vec<&'a std::ffi::CStr>,
+    validation_features: &[vk::ValidationFeatureEnableEXT],
 ) -> Result<ash::Instance, String> {
     log::info!("creating instance");
 
@@ -11,15 +13,32 @@ pub fn create_instance<'a>(
         .map(|ext| ext.as_ptr())
         .collect::<Vec<_>>();
 
+    let layer_names_raw = layers
+        .iter()
+        .map(|layer| layer.as_ptr())
+        .collect::<Vec<_>>();
+
+    // 1.3 features are used where the device supports them, 1.2 is still the minimum
     let app_info = vk::ApplicationInfo::builder()
-        .api_version(vk::make_api_version(0, 1, 2, 0))
+        .api_version(vk::make_api_version(0, 1, 3, 0))
         .build();
 
-    let create_info = vk::InstanceCreateInfo::builder()
-        .enabled_extension_names(&extension_names_raw)
-        .application_info(&app_info)
+    // only chained when VK_EXT_validation_features is among the extensions
+    let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+        .enabled_validation_features(validation_features)
         .build();
 
+    let mut create_info_builder = vk::InstanceCreateInfo::builder()
+        .enabled_extension_names(&extension_names_raw)
+        .enabled_layer_names(&layer_names_raw)
+        .application_info(&app_info);
+
+    if !validation_features.is_empty() {
+        create_info_builder = create_info_builder.push_next(&mut validation_features_info);
+    }
+
+    let create_info = create_info_builder.build();
+
     let instance = unsafe {
         entry
             .create_instance(&create_info, None)
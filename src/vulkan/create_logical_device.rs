@@ -1,13 +1,40 @@
 use ash::vk;
 
+use super::SubgroupSizeControl;
+
+// optional features enabled on the device, their extensions have to be enabled as well
+#[derive(Clone, Copy, Default)]
+pub struct EnabledFeatures<'a> {
+    // cooperative matrices and f16, for the cooperative matrix kernel
+    pub cooperative_matrix: bool,
+    // required subgroup sizes, None when the device cannot control them
+    pub subgroup_size_control: Option<&'a SubgroupSizeControl>,
+    // the SM of each workgroup, for the profiling kernels
+    pub shader_sm_builtins: bool,
+    pub pipeline_statistics_query: bool,
+    pub performance_query: bool,
+    // the barriers of task graphs
+    pub synchronization2: bool,
+}
+
 pub fn create_logical_device<'a>(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     queue_family: u32,
     device_extensions: &Vec<&'a std::ffi::CStr>,
+    enabled_features: &EnabledFeatures,
 ) -> Result<ash::Device, String> {
     log::info!("creating logical device");
 
+    let EnabledFeatures {
+        cooperative_matrix,
+        subgroup_size_control,
+        shader_sm_builtins,
+        pipeline_statistics_query,
+        performance_query,
+        synchronization2,
+    } = *enabled_features;
+
     let queue_indices = [queue_family];
 
     let mut queue_priorities = Vec::new();
@@ -27,16 +54,76 @@ pub fn create_logical_device<'a>(
 
     let features = vk::PhysicalDeviceFeatures::builder()
         .shader_int64(true)
+        .pipeline_statistics_query(pipeline_statistics_query)
         .build();
 
     let mut shader_clock_features = vk::PhysicalDeviceShaderClockFeaturesKHR::builder()
         .shader_device_clock(true)
         .build();
 
+    // needed for the cooperative matrix kernel
+    let mut cooperative_matrix_features = vk::PhysicalDeviceCooperativeMatrixFeaturesNV::builder()
+        .cooperative_matrix(true)
+        .build();
+
+    // f16 for the cooperative matrix kernel, host resets for the performance queries
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .shader_float16(cooperative_matrix)
+        .host_query_reset(performance_query)
+        .build();
+
+    let mut performance_query_features = vk::PhysicalDevicePerformanceQueryFeaturesKHR::builder()
+        .performance_counter_query_pools(true)
+        .build();
+
+    // needed for required subgroup sizes
+    let mut subgroup_size_control_features =
+        vk::PhysicalDeviceSubgroupSizeControlFeatures::builder()
+            .subgroup_size_control(true)
+            .compute_full_subgroups(
+                subgroup_size_control.is_some_and(|control| control.compute_full_subgroups),
+            )
+            .build();
+
+    // needed by the profiling kernels recording the SM of each workgroup
+    let mut shader_sm_builtins_features = vk::PhysicalDeviceShaderSMBuiltinsFeaturesNV::builder()
+        .shader_sm_builtins(true)
+        .build();
+
+    // the barriers of task graphs
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::builder()
+        .synchronization2(true)
+        .build();
+
     let mut features2 = vk::PhysicalDeviceFeatures2::builder()
         .features(features)
-        .push_next(&mut shader_clock_features)
-        .build();
+        .push_next(&mut shader_clock_features);
+
+    if subgroup_size_control.is_some() {
+        features2 = features2.push_next(&mut subgroup_size_control_features);
+    }
+
+    if shader_sm_builtins {
+        features2 = features2.push_next(&mut shader_sm_builtins_features);
+    }
+
+    if cooperative_matrix {
+        features2 = features2.push_next(&mut cooperative_matrix_features);
+    }
+
+    if performance_query {
+        features2 = features2.push_next(&mut performance_query_features);
+    }
+
+    if synchronization2 {
+        features2 = features2.push_next(&mut synchronization2_features);
+    }
+
+    if cooperative_matrix || performance_query {
+        features2 = features2.push_next(&mut vulkan_12_features);
+    }
+
+    let mut features2 = features2.build();
 
     let device_extensions_raw = device_extensions
         .iter()
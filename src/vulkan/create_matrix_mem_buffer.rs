@@ -0,0 +1,42 @@
+use ash::vk;
+
+use super::{DeviceCapabilities, MemBuffer};
+
+// matrices in device local memory the host can map skip the staging copies, the smaller BAR
+// heap of discrete GPUs falls back to plain device local memory
+pub fn create_matrix_mem_buffer(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &ash::Device,
+    device_capabilities: &DeviceCapabilities,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> Result<MemBuffer, String> {
+    if device_capabilities.host_visible_device_local {
+        match super::create_mem_buffer_preferring(
+            instance,
+            physical_device,
+            device,
+            size,
+            usage,
+            &[
+                super::HOST_VISIBLE_DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_COHERENT,
+                super::HOST_VISIBLE_DEVICE_LOCAL,
+            ],
+            device_capabilities.memory_budget,
+        ) {
+            Ok(mem_buffer) => return Ok(mem_buffer),
+            Err(msg) => log::warn!("matrix buffer is not host visible: {}", msg),
+        }
+    }
+
+    super::create_mem_buffer(
+        instance,
+        physical_device,
+        device,
+        size,
+        usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        device_capabilities.memory_budget,
+    )
+}
@@ -1,9 +1,34 @@
 use ash::vk;
 
+// device local memory the host writes and reads through a mapping
+pub const HOST_VISIBLE_DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+    vk::MemoryPropertyFlags::DEVICE_LOCAL.as_raw() | vk::MemoryPropertyFlags::HOST_VISIBLE.as_raw(),
+);
+
+// the default has null handles, which are ignored when destroyed
+#[derive(Default)]
 pub struct MemBuffer {
     pub buffer: vk::Buffer,
     pub device_memory: vk::DeviceMemory,
     pub size: vk::DeviceSize,
+    // at least `size`, the end of the memory for flushes and invalidations
+    pub allocation_size: vk::DeviceSize,
+    // property flags of the memory type the buffer was bound to
+    pub memory_flags: vk::MemoryPropertyFlags,
+}
+
+impl MemBuffer {
+    // written and read through a mapping, without a staging copy
+    pub fn host_mapped(&self) -> bool {
+        self.memory_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+    }
+
+    // host writes have to be flushed and device writes invalidated
+    pub fn host_coherent(&self) -> bool {
+        self.memory_flags
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
 }
 
 pub fn create_mem_buffer(
@@ -13,16 +38,68 @@ pub fn create_mem_buffer(
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     memory_flags: vk::MemoryPropertyFlags,
+    memory_budget_supported: bool,
+) -> Result<MemBuffer, String> {
+    create_mem_buffer_preferring(
+        instance,
+        physical_device,
+        device,
+        size,
+        usage,
+        &[memory_flags],
+        memory_budget_supported,
+    )
+}
+
+// binds the first memory type that has all flags of one of the candidates, tried in order
+pub fn create_mem_buffer_preferring(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: &ash::Device,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    memory_flag_candidates: &[vk::MemoryPropertyFlags],
+    memory_budget_supported: bool,
 ) -> Result<MemBuffer, String> {
     log::info!("creating mem buffer");
 
     let buffer = create_buffer(device, size, usage)?;
 
-    let memory_type =
-        find_buffer_memory_type(instance, physical_device, device, buffer, memory_flags)?;
+    let memory_type = match find_buffer_memory_type(
+        instance,
+        physical_device,
+        device,
+        buffer,
+        memory_flag_candidates,
+    ) {
+        Ok(memory_type) => memory_type,
+        Err(msg) => {
+            unsafe {
+                device.destroy_buffer(buffer, None);
+            }
+
+            return Err(msg);
+        }
+    };
+
+    // refused up front, drivers may otherwise page or fail with an unspecific error
+    let memory_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+    if let Err(msg) = super::get_memory_budget(instance, physical_device, memory_budget_supported)
+        .check_allocation(memory_type, memory_requirements.size)
+    {
+        unsafe {
+            device.destroy_buffer(buffer, None);
+        }
+
+        return Err(msg);
+    }
 
     let device_memory = create_device_memory(device, buffer, memory_type)?;
 
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
     unsafe {
         device
             .bind_buffer_memory(buffer, device_memory, 0)
@@ -33,6 +110,8 @@ pub fn create_mem_buffer(
         buffer,
         device_memory,
         size,
+        allocation_size: memory_requirements.size,
+        memory_flags: memory_properties.memory_types[memory_type as usize].property_flags,
     })
 }
 
@@ -61,18 +140,22 @@ fn find_buffer_memory_type(
     physical_device: vk::PhysicalDevice,
     device: &ash::Device,
     buffer: vk::Buffer,
-    memory_flags: vk::MemoryPropertyFlags,
+    memory_flag_candidates: &[vk::MemoryPropertyFlags],
 ) -> Result<u32, String> {
     let memory_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
 
-    let memory_property_index = get_supported_memory_property_index(
-        instance,
-        physical_device,
-        memory_requirements.memory_type_bits,
-        memory_flags,
-    )?;
-
-    Ok(memory_property_index)
+    memory_flag_candidates
+        .iter()
+        .find_map(|&memory_flags| {
+            get_supported_memory_property_index(
+                instance,
+                physical_device,
+                memory_requirements.memory_type_bits,
+                memory_flags,
+            )
+            .ok()
+        })
+        .ok_or_else(|| String::from("failed to find memory property index"))
 }
 
 fn get_supported_memory_property_index(
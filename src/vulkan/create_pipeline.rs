@@ -1,54 +1,61 @@
 use ash::vk;
 
-use crate::constants;
+use crate::shader_variants::ShaderVariant;
+
+use super::KernelConfig;
 
 pub fn create_pipeline(
     device: &ash::Device,
     shader_module: vk::ShaderModule,
     pipeline_layout: vk::PipelineLayout,
+    shader_variant: ShaderVariant,
+    kernel_config: &KernelConfig,
 ) -> Result<vk::Pipeline, String> {
-    log::info!("creating pipeline");
+    log::info!("creating pipeline {:?}", kernel_config);
 
     let shader_entry_name = std::ffi::CString::new("main").unwrap();
 
-    let map_entry_0 = vk::SpecializationMapEntry::builder()
-        .constant_id(0)
-        .offset(0)
-        .size(std::mem::size_of::<u32>())
-        .build();
-
-    let map_entry_1 = vk::SpecializationMapEntry::builder()
-        .constant_id(1)
-        .offset(std::mem::size_of::<u32>() as u32)
-        .size(std::mem::size_of::<u32>())
-        .build();
-
-    let map_entry_2 = vk::SpecializationMapEntry::builder()
-        .constant_id(2)
-        .offset(2 * std::mem::size_of::<u32>() as u32)
-        .size(std::mem::size_of::<u32>())
-        .build();
+    let data = kernel_config.specialization_data(shader_variant);
 
-    let map_entries = [map_entry_0, map_entry_1, map_entry_2];
+    let map_entries = (0..data.len() as u32)
+        .map(|constant_id| {
+            vk::SpecializationMapEntry::builder()
+                .constant_id(constant_id)
+                .offset(constant_id * std::mem::size_of::<u32>() as u32)
+                .size(std::mem::size_of::<u32>())
+                .build()
+        })
+        .collect::<Vec<_>>();
 
-    let data_0 = constants::WORKGROUP_SIZE.to_ne_bytes(); // local_size_x_id
-    let data_1 = constants::WORKGROUP_SIZE.to_ne_bytes(); // local_size_y_id
-    let data_2 = 1u32.to_ne_bytes(); // local_size_z_id
-    let data_3 = constants::WORKGROUP_SIZE.to_ne_bytes(); // shared memory element count X and Y
-
-    let data = [data_0, data_1, data_2, data_3].concat();
+    let data = data
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect::<Vec<_>>();
 
     let specialization_info = vk::SpecializationInfo::builder()
         .map_entries(&map_entries)
         .data(&data)
         .build();
 
-    let pipeline_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
+    let mut required_subgroup_size =
+        vk::PipelineShaderStageRequiredSubgroupSizeCreateInfo::builder()
+            .required_subgroup_size(kernel_config.subgroup_size)
+            .build();
+
+    let mut pipeline_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::COMPUTE)
         .module(shader_module)
         .name(&shader_entry_name)
-        .specialization_info(&specialization_info)
-        .build();
+        .specialization_info(&specialization_info);
+
+    // a required subgroup size comes with full subgroups so gl_SubgroupSize is exact
+    if kernel_config.subgroup_size != 0 {
+        pipeline_shader_stage = pipeline_shader_stage
+            .flags(vk::PipelineShaderStageCreateFlags::REQUIRE_FULL_SUBGROUPS)
+            .push_next(&mut required_subgroup_size);
+    }
+
+    let pipeline_shader_stage = pipeline_shader_stage.build();
 
     let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
         .flags(vk::PipelineCreateFlags::empty())
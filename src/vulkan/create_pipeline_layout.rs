@@ -3,20 +3,25 @@ use ash::vk;
 pub fn create_pipeline_layout(
     device: &ash::Device,
     descriptor_set_layout: vk::DescriptorSetLayout,
+    push_constant_size: u32,
 ) -> Result<vk::PipelineLayout, String> {
     log::info!("creating pipeline layout");
 
     let push_const_range = vk::PushConstantRange {
         stage_flags: vk::ShaderStageFlags::COMPUTE,
         offset: 0,
-        size: 4,
+        size: push_constant_size,
     };
 
     let layouts = [descriptor_set_layout];
-    let ranges = [push_const_range];
+    let ranges: &[vk::PushConstantRange] = if push_constant_size > 0 {
+        &[push_const_range]
+    } else {
+        &[]
+    };
     let create_info = vk::PipelineLayoutCreateInfo::builder()
         .set_layouts(&layouts)
-        .push_constant_ranges(&ranges)
+        .push_constant_ranges(ranges)
         .build();
 
     let pipeline_layout = unsafe {
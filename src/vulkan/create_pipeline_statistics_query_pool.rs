@@ -0,0 +1,22 @@
+use ash::vk;
+
+// a single query counting compute shader invocations
+pub fn create_pipeline_statistics_query_pool(
+    device: &ash::Device,
+) -> Result<vk::QueryPool, String> {
+    log::info!("creating pipeline statistics query pool");
+
+    let create_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::PIPELINE_STATISTICS)
+        .query_count(1)
+        .pipeline_statistics(vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS)
+        .build();
+
+    let query_pool = unsafe {
+        device
+            .create_query_pool(&create_info, None)
+            .map_err(|_| String::from("failed to create pipeline statistics query pool"))?
+    };
+
+    Ok(query_pool)
+}
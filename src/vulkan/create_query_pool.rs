@@ -1,9 +1,11 @@
 use ash::vk;
 
-pub fn create_query_pool(device: &ash::Device) -> Result<vk::QueryPool, String> {
+pub fn create_query_pool(device: &ash::Device, query_count: u32) -> Result<vk::QueryPool, String> {
+    log::info!("creating query pool");
+
     let create_info = vk::QueryPoolCreateInfo::builder()
         .query_type(vk::QueryType::TIMESTAMP)
-        .query_count(2)
+        .query_count(query_count)
         .build();
 
     let query_pool = unsafe {
@@ -0,0 +1,266 @@
+use ash::extensions::ext;
+use ash::vk;
+
+use std::sync::Mutex;
+
+pub fn validation_layer_name() -> &'static std::ffi::CStr {
+    std::ffi::CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap()
+}
+
+// opt-in validation through VK_LAYER_KHRONOS_validation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    Disabled,
+    // messages are routed to the log
+    Enabled,
+    // validation errors also fail the call during which they were reported
+    FailOnError,
+    // debugPrintfEXT output of the kernels is captured and returned with each dispatch
+    DebugPrintf,
+}
+
+// what the callback keeps besides logging
+#[derive(Default)]
+struct CapturedMessages {
+    validation_errors: Vec<String>,
+    printf_messages: Vec<String>,
+}
+
+// routes driver and layer messages to the `log` crate and keeps the validation errors
+pub struct DebugMessenger {
+    debug_utils_loader: ext::DebugUtils,
+    messenger: vk::DebugUtilsMessengerEXT,
+    // boxed so the callback can keep a pointer to it
+    captured: Box<Mutex<CapturedMessages>>,
+}
+
+const PRINTF_MESSAGE_ID: &str = "MessageID = ";
+
+// debugPrintfEXT messages are reported by the layer with this id name
+fn is_printf_message(callback_data: &vk::DebugUtilsMessengerCallbackDataEXT) -> bool {
+    if callback_data.p_message_id_name.is_null() {
+        return false;
+    }
+
+    let id_name = unsafe { std::ffi::CStr::from_ptr(callback_data.p_message_id_name) };
+
+    id_name.to_string_lossy().contains("DEBUG-PRINTF")
+}
+
+// the layer prefixes the printed text with the objects and the message id, e.g.
+// "Validation Information: [ ... ] Object 0: ...; | MessageID = 0x92394c89 | text"; the text
+// follows the separator after the id and may contain separators itself
+fn printf_text(message: &str) -> &str {
+    message
+        .find(PRINTF_MESSAGE_ID)
+        .and_then(|ind| {
+            let id = &message[ind..];
+            id.find(" | ").map(|end| &id[end + 3..])
+        })
+        .unwrap_or(message)
+}
+
+unsafe extern "system" fn debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut std::ffi::c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() || (*p_callback_data).p_message.is_null() {
+        return vk::FALSE;
+    }
+
+    let message = std::ffi::CStr::from_ptr((*p_callback_data).p_message).to_string_lossy();
+
+    let captured = match p_user_data.is_null() {
+        true => None,
+        false => Some(&*(p_user_data as *const Mutex<CapturedMessages>)),
+    };
+
+    if is_printf_message(&*p_callback_data) {
+        let text = printf_text(&message).trim_end();
+
+        log::info!("debug printf: {}", text);
+
+        if let Some(Ok(mut captured)) = captured.map(|captured| captured.lock()) {
+            captured.printf_messages.push(String::from(text));
+        }
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::error!("{}", message);
+
+        if message_types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            if let Some(Ok(mut captured)) = captured.map(|captured| captured.lock()) {
+                captured.validation_errors.push(message.into_owned());
+            }
+        }
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::warn!("{}", message);
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::info!("{}", message);
+    } else {
+        log::debug!("{}", message);
+    }
+
+    // the call that triggered the message must not be aborted
+    vk::FALSE
+}
+
+impl DebugMessenger {
+    pub fn new(entry: &ash::Entry, instance: &ash::Instance) -> Result<Self, String> {
+        log::info!("creating debug messenger");
+
+        let debug_utils_loader = ext::DebugUtils::new(entry, instance);
+
+        let captured = Box::new(Mutex::new(CapturedMessages::default()));
+
+        let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(
+                vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            )
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(debug_callback))
+            .user_data(&*captured as *const Mutex<CapturedMessages> as *mut std::ffi::c_void)
+            .build();
+
+        let messenger = unsafe {
+            debug_utils_loader
+                .create_debug_utils_messenger(&create_info, None)
+                .map_err(|_| String::from("failed to create debug messenger"))?
+        };
+
+        Ok(Self {
+            debug_utils_loader,
+            messenger,
+            captured,
+        })
+    }
+
+    // validation errors reported since the last call
+    pub fn take_errors(&self) -> Vec<String> {
+        match self.captured.lock() {
+            Ok(mut captured) => std::mem::take(&mut captured.validation_errors),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // debugPrintfEXT output reported since the last call
+    pub fn take_printf_messages(&self) -> Vec<String> {
+        match self.captured.lock() {
+            Ok(mut captured) => std::mem::take(&mut captured.printf_messages),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn clean(self) {
+        log::info!("cleaning debug messenger");
+
+        unsafe {
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.messenger, None);
+        }
+    }
+}
+
+pub fn is_validation_layer_supported(entry: &ash::Entry) -> bool {
+    let layers = match entry.enumerate_instance_layer_properties() {
+        Ok(layers) => layers,
+        Err(_) => return false,
+    };
+
+    layers.iter().any(|layer| {
+        let layer_name = unsafe { std::ffi::CStr::from_ptr(layer.layer_name.as_ptr()) };
+        layer_name == validation_layer_name()
+    })
+}
+
+// debug printf is enabled through VK_EXT_validation_features, provided by the validation layer
+pub fn is_debug_printf_supported(entry: &ash::Entry) -> bool {
+    let extensions =
+        match entry.enumerate_instance_extension_properties(Some(validation_layer_name())) {
+            Ok(extensions) => extensions,
+            Err(_) => return false,
+        };
+
+    extensions.iter().any(|extension| {
+        let extension_name = unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) };
+        extension_name == vk::ExtValidationFeaturesFn::name()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printf_text_keeps_separators_of_the_output() {
+        let message = "Validation Information: [ UNASSIGNED-DEBUG-PRINTF ] Object 0: handle = \
+                       0x55d1, type = VK_OBJECT_TYPE_QUEUE; | MessageID = 0x92394c89 | a | b";
+
+        assert_eq!(printf_text(message), "a | b");
+    }
+
+    #[test]
+    fn printf_text_without_prefix() {
+        assert_eq!(printf_text("a | b"), "a | b");
+    }
+
+    // submits a message the way the layer reports debugPrintfEXT output and checks that the
+    // messenger captures its text; skipped without the validation layer
+    #[test]
+    fn captures_printf_messages() {
+        let entry = super::super::create_entry();
+
+        if !is_validation_layer_supported(&entry) {
+            eprintln!("validation layer is not installed, skipping");
+            return;
+        }
+
+        let instance = super::super::create_instance(
+            &entry,
+            &vec![ext::DebugUtils::name()],
+            &vec![validation_layer_name()],
+            &[],
+        )
+        .unwrap();
+
+        let debug_messenger = DebugMessenger::new(&entry, &instance).unwrap();
+
+        let id_name = std::ffi::CString::new("WARNING-DEBUG-PRINTF").unwrap();
+        let message = std::ffi::CString::new(
+            "Validation Information: [ WARNING-DEBUG-PRINTF ] | MessageID = 0x76589099 | c = 1 | 2\n",
+        )
+        .unwrap();
+
+        let callback_data = vk::DebugUtilsMessengerCallbackDataEXT::builder()
+            .message_id_name(&id_name)
+            .message(&message)
+            .build();
+
+        unsafe {
+            debug_messenger
+                .debug_utils_loader
+                .submit_debug_utils_message(
+                    vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+                    vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                    &callback_data,
+                );
+        }
+
+        assert_eq!(debug_messenger.take_printf_messages(), ["c = 1 | 2"]);
+        assert!(debug_messenger.take_printf_messages().is_empty());
+        assert!(debug_messenger.take_errors().is_empty());
+
+        debug_messenger.clean();
+
+        unsafe {
+            instance.destroy_instance(None);
+        }
+    }
+}
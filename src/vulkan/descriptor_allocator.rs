@@ -0,0 +1,632 @@
+use ash::vk;
+
+use std::collections::HashMap;
+
+use crate::shader_interface::ShaderBinding;
+
+// sets of the first pool, every further pool holds twice as many as the one before
+const INITIAL_POOL_SETS: u32 = 8;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DescriptorKey {
+    layout: vk::DescriptorSetLayout,
+    shader_bindings: Vec<ShaderBinding>,
+    buffers: Vec<vk::Buffer>,
+}
+
+// a layout is shared by variants that bind fewer buffers than it was created with, so a
+// template only fits the bindings it was created for
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TemplateKey {
+    layout: vk::DescriptorSetLayout,
+    shader_bindings: Vec<ShaderBinding>,
+}
+
+struct CachedSet {
+    set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+}
+
+// hands out descriptor sets of a layout with buffers bound, written once and kept until one of
+// the buffers is forgotten; the pools grow on demand, so sets stay valid while other work
+// allocates. the buffers are written through an update template when the device has them
+pub struct DescriptorAllocator {
+    pools: Vec<vk::DescriptorPool>,
+    next_pool_sets: u32,
+    cache: HashMap<DescriptorKey, CachedSet>,
+    // one per layout and bindings, None without update templates
+    update_templates: Option<HashMap<TemplateKey, vk::DescriptorUpdateTemplate>>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(update_templates: bool) -> Self {
+        log::info!("creating descriptor allocator");
+
+        Self {
+            pools: Vec::new(),
+            next_pool_sets: INITIAL_POOL_SETS,
+            cache: HashMap::new(),
+            update_templates: update_templates.then(HashMap::new),
+        }
+    }
+
+    // `buffers` are bound in the order of `shader_bindings`, `descriptor_count` each
+    pub fn descriptor_set(
+        &mut self,
+        device: &impl DescriptorDevice,
+        layout: vk::DescriptorSetLayout,
+        shader_bindings: &[ShaderBinding],
+        buffers: &[vk::Buffer],
+    ) -> Result<vk::DescriptorSet, String> {
+        let descriptor_count = shader_bindings
+            .iter()
+            .map(|b| b.descriptor_count as usize)
+            .sum::<usize>();
+
+        if buffers.len() != descriptor_count {
+            return Err(format!(
+                "{} buffers for a layout of {} descriptors",
+                buffers.len(),
+                descriptor_count
+            ));
+        }
+
+        let key = DescriptorKey {
+            layout,
+            shader_bindings: shader_bindings.to_vec(),
+            buffers: buffers.to_vec(),
+        };
+
+        if let Some(cached) = self.cache.get(&key) {
+            return Ok(cached.set);
+        }
+
+        let (set, pool) = self.allocate(device, layout, shader_bindings)?;
+
+        if let Err(msg) = self.write(device, set, layout, shader_bindings, buffers) {
+            let _ = device.free_set(pool, set);
+            return Err(msg);
+        }
+
+        self.cache.insert(key, CachedSet { set, pool });
+
+        Ok(set)
+    }
+
+    // frees the sets `buffer` is bound to, before the buffer is destroyed; work using them has
+    // to be complete
+    pub fn forget_buffer(&mut self, device: &impl DescriptorDevice, buffer: vk::Buffer) {
+        let keys = self
+            .cache
+            .keys()
+            .filter(|key| key.buffers.contains(&buffer))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            if let Some(cached) = self.cache.remove(&key) {
+                if device.free_set(cached.pool, cached.set).is_err() {
+                    log::error!("failed to free descriptor set");
+                }
+            }
+        }
+    }
+
+    pub fn clean(&mut self, device: &impl DescriptorDevice) {
+        self.cache.clear();
+
+        for pool in self.pools.drain(..) {
+            device.destroy_pool(pool);
+        }
+
+        if let Some(update_templates) = &mut self.update_templates {
+            for (_, update_template) in update_templates.drain() {
+                device.destroy_update_template(update_template);
+            }
+        }
+    }
+
+    // from the newest pool with room, or from a new pool twice the size of the last
+    fn allocate(
+        &mut self,
+        device: &impl DescriptorDevice,
+        layout: vk::DescriptorSetLayout,
+        shader_bindings: &[ShaderBinding],
+    ) -> Result<(vk::DescriptorSet, vk::DescriptorPool), String> {
+        for &pool in self.pools.iter().rev() {
+            match device.allocate_set(pool, layout) {
+                Ok(set) => return Ok((set, pool)),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(_) => return Err(String::from("failed to allocate descriptor set")),
+            }
+        }
+
+        log::info!(
+            "growing descriptor allocator by {} sets",
+            self.next_pool_sets
+        );
+
+        let pool = device.create_pool(shader_bindings, self.next_pool_sets)?;
+
+        self.pools.push(pool);
+        self.next_pool_sets *= 2;
+
+        let set = device
+            .allocate_set(pool, layout)
+            .map_err(|_| String::from("failed to allocate descriptor set"))?;
+
+        Ok((set, pool))
+    }
+
+    fn write(
+        &mut self,
+        device: &impl DescriptorDevice,
+        set: vk::DescriptorSet,
+        layout: vk::DescriptorSetLayout,
+        shader_bindings: &[ShaderBinding],
+        buffers: &[vk::Buffer],
+    ) -> Result<(), String> {
+        // the template and the writes read one buffer info per descriptor
+        assert_eq!(
+            buffers.len(),
+            shader_bindings
+                .iter()
+                .map(|b| b.descriptor_count as usize)
+                .sum::<usize>(),
+            "buffers do not match the descriptors of the bindings"
+        );
+
+        let update_template = match &mut self.update_templates {
+            Some(update_templates) => {
+                let template_key = TemplateKey {
+                    layout,
+                    shader_bindings: shader_bindings.to_vec(),
+                };
+
+                Some(match update_templates.get(&template_key) {
+                    Some(&update_template) => update_template,
+                    None => {
+                        let update_template =
+                            device.create_update_template(layout, shader_bindings)?;
+                        update_templates.insert(template_key, update_template);
+                        update_template
+                    }
+                })
+            }
+            None => None,
+        };
+
+        device.write_set(set, update_template, shader_bindings, buffers);
+
+        Ok(())
+    }
+}
+
+// the Vulkan calls of the allocator, apart from its bookkeeping so that can be tested without a
+// device
+pub trait DescriptorDevice {
+    fn create_pool(
+        &self,
+        shader_bindings: &[ShaderBinding],
+        max_sets: u32,
+    ) -> Result<vk::DescriptorPool, String>;
+
+    fn destroy_pool(&self, pool: vk::DescriptorPool);
+
+    fn allocate_set(
+        &self,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, vk::Result>;
+
+    fn free_set(&self, pool: vk::DescriptorPool, set: vk::DescriptorSet) -> Result<(), vk::Result>;
+
+    fn create_update_template(
+        &self,
+        layout: vk::DescriptorSetLayout,
+        shader_bindings: &[ShaderBinding],
+    ) -> Result<vk::DescriptorUpdateTemplate, String>;
+
+    fn destroy_update_template(&self, update_template: vk::DescriptorUpdateTemplate);
+
+    // `buffers` in the order of `shader_bindings`, through the template if there is one
+    fn write_set(
+        &self,
+        set: vk::DescriptorSet,
+        update_template: Option<vk::DescriptorUpdateTemplate>,
+        shader_bindings: &[ShaderBinding],
+        buffers: &[vk::Buffer],
+    );
+}
+
+impl DescriptorDevice for ash::Device {
+    fn create_pool(
+        &self,
+        shader_bindings: &[ShaderBinding],
+        max_sets: u32,
+    ) -> Result<vk::DescriptorPool, String> {
+        // sets are freed one by one when their buffers are forgotten
+        super::create_descriptor_pool(
+            self,
+            shader_bindings,
+            max_sets,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+        )
+    }
+
+    fn destroy_pool(&self, pool: vk::DescriptorPool) {
+        unsafe {
+            self.destroy_descriptor_pool(pool, None);
+        }
+    }
+
+    fn allocate_set(
+        &self,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, vk::Result> {
+        let layouts = [layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts)
+            .build();
+
+        unsafe { self.allocate_descriptor_sets(&alloc_info) }.map(|sets| sets[0])
+    }
+
+    fn free_set(&self, pool: vk::DescriptorPool, set: vk::DescriptorSet) -> Result<(), vk::Result> {
+        unsafe { self.free_descriptor_sets(pool, &[set]) }
+    }
+
+    // reads the buffer infos of all bindings from one array, in the order of `shader_bindings`
+    fn create_update_template(
+        &self,
+        layout: vk::DescriptorSetLayout,
+        shader_bindings: &[ShaderBinding],
+    ) -> Result<vk::DescriptorUpdateTemplate, String> {
+        log::info!("creating descriptor update template");
+
+        let stride = std::mem::size_of::<vk::DescriptorBufferInfo>();
+
+        let mut first_info = 0;
+
+        let entries = shader_bindings
+            .iter()
+            .map(|shader_binding| {
+                let entry = vk::DescriptorUpdateTemplateEntry::builder()
+                    .dst_binding(shader_binding.binding)
+                    .dst_array_element(0)
+                    .descriptor_count(shader_binding.descriptor_count)
+                    .descriptor_type(shader_binding.descriptor_type)
+                    .offset(first_info * stride)
+                    .stride(stride)
+                    .build();
+
+                first_info += shader_binding.descriptor_count as usize;
+
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        let create_info = vk::DescriptorUpdateTemplateCreateInfo::builder()
+            .descriptor_update_entries(&entries)
+            .template_type(vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET)
+            .descriptor_set_layout(layout)
+            .build();
+
+        unsafe {
+            self.create_descriptor_update_template(&create_info, None)
+                .map_err(|_| String::from("failed to create descriptor update template"))
+        }
+    }
+
+    fn destroy_update_template(&self, update_template: vk::DescriptorUpdateTemplate) {
+        unsafe {
+            self.destroy_descriptor_update_template(update_template, None);
+        }
+    }
+
+    fn write_set(
+        &self,
+        set: vk::DescriptorSet,
+        update_template: Option<vk::DescriptorUpdateTemplate>,
+        shader_bindings: &[ShaderBinding],
+        buffers: &[vk::Buffer],
+    ) {
+        let infos = buffers
+            .iter()
+            .map(|&buffer| {
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(update_template) = update_template {
+            unsafe {
+                self.update_descriptor_set_with_template(
+                    set,
+                    update_template,
+                    infos.as_ptr().cast(),
+                );
+            }
+
+            return;
+        }
+
+        let mut first_info = 0;
+
+        let write_descriptor_sets = shader_bindings
+            .iter()
+            .map(|shader_binding| {
+                let binding_infos =
+                    &infos[first_info..first_info + shader_binding.descriptor_count as usize];
+                first_info += binding_infos.len();
+
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(shader_binding.binding)
+                    .descriptor_type(shader_binding.descriptor_type)
+                    .buffer_info(binding_infos)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            self.update_descriptor_sets(&write_descriptor_sets, &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+    use std::cell::{Cell, RefCell};
+
+    // pools that run out after their sets, handles are counted up from 1
+    #[derive(Default)]
+    struct FakeDevice {
+        next_handle: Cell<u64>,
+        // capacity and sets allocated of each pool
+        pools: RefCell<HashMap<vk::DescriptorPool, (u32, u32)>>,
+        allocated_sets: Cell<usize>,
+        created_templates: Cell<usize>,
+        writes: RefCell<Vec<(vk::DescriptorSet, Option<vk::DescriptorUpdateTemplate>)>>,
+    }
+
+    impl FakeDevice {
+        fn handle(&self) -> u64 {
+            self.next_handle.set(self.next_handle.get() + 1);
+            self.next_handle.get()
+        }
+    }
+
+    impl DescriptorDevice for FakeDevice {
+        fn create_pool(
+            &self,
+            _shader_bindings: &[ShaderBinding],
+            max_sets: u32,
+        ) -> Result<vk::DescriptorPool, String> {
+            let pool = vk::DescriptorPool::from_raw(self.handle());
+            self.pools.borrow_mut().insert(pool, (max_sets, 0));
+            Ok(pool)
+        }
+
+        fn destroy_pool(&self, pool: vk::DescriptorPool) {
+            self.pools.borrow_mut().remove(&pool);
+        }
+
+        fn allocate_set(
+            &self,
+            pool: vk::DescriptorPool,
+            _layout: vk::DescriptorSetLayout,
+        ) -> Result<vk::DescriptorSet, vk::Result> {
+            let mut pools = self.pools.borrow_mut();
+            let (max_sets, sets) = pools.get_mut(&pool).unwrap();
+
+            if *sets == *max_sets {
+                return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+            }
+
+            *sets += 1;
+            self.allocated_sets.set(self.allocated_sets.get() + 1);
+
+            Ok(vk::DescriptorSet::from_raw(self.handle()))
+        }
+
+        fn free_set(
+            &self,
+            pool: vk::DescriptorPool,
+            _set: vk::DescriptorSet,
+        ) -> Result<(), vk::Result> {
+            self.pools.borrow_mut().get_mut(&pool).unwrap().1 -= 1;
+            Ok(())
+        }
+
+        fn create_update_template(
+            &self,
+            _layout: vk::DescriptorSetLayout,
+            _shader_bindings: &[ShaderBinding],
+        ) -> Result<vk::DescriptorUpdateTemplate, String> {
+            self.created_templates.set(self.created_templates.get() + 1);
+            Ok(vk::DescriptorUpdateTemplate::from_raw(self.handle()))
+        }
+
+        fn destroy_update_template(&self, _update_template: vk::DescriptorUpdateTemplate) {}
+
+        fn write_set(
+            &self,
+            set: vk::DescriptorSet,
+            update_template: Option<vk::DescriptorUpdateTemplate>,
+            _shader_bindings: &[ShaderBinding],
+            _buffers: &[vk::Buffer],
+        ) {
+            self.writes.borrow_mut().push((set, update_template));
+        }
+    }
+
+    fn binding(binding: u32) -> ShaderBinding {
+        ShaderBinding {
+            set: 0,
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            name: "",
+            read_only: false,
+        }
+    }
+
+    fn layout(layout: u64) -> vk::DescriptorSetLayout {
+        vk::DescriptorSetLayout::from_raw(layout)
+    }
+
+    fn buffers(buffers: &[u64]) -> Vec<vk::Buffer> {
+        buffers.iter().map(|&b| vk::Buffer::from_raw(b)).collect()
+    }
+
+    #[test]
+    fn same_layout_and_buffers_hit_the_cache() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(false);
+        let bindings = [binding(0), binding(1)];
+
+        let set = allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+
+        assert_eq!(
+            allocator
+                .descriptor_set(&device, layout(1), &bindings, &buffers(&[10, 11]))
+                .unwrap(),
+            set
+        );
+        assert_eq!(device.allocated_sets.get(), 1);
+        assert_eq!(device.writes.borrow().len(), 1);
+
+        // other buffers or another layout need their own set
+        let other_buffers = allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[11, 10]))
+            .unwrap();
+        let other_layout = allocator
+            .descriptor_set(&device, layout(2), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+
+        assert_ne!(other_buffers, set);
+        assert_ne!(other_layout, set);
+        assert_eq!(device.allocated_sets.get(), 3);
+    }
+
+    #[test]
+    fn forgotten_buffers_miss_the_cache() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(false);
+        let bindings = [binding(0), binding(1)];
+
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[12, 13]))
+            .unwrap();
+
+        allocator.forget_buffer(&device, vk::Buffer::from_raw(11));
+
+        // the set of 10 and 11 went back to its pool, the other one is still cached
+        assert_eq!(device.pools.borrow().values().next(), Some(&(8, 1)));
+
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[12, 13]))
+            .unwrap();
+
+        assert_eq!(device.allocated_sets.get(), 3);
+    }
+
+    #[test]
+    fn full_pools_are_followed_by_larger_ones() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(false);
+        let bindings = [binding(0)];
+
+        for buffer in 0..INITIAL_POOL_SETS as u64 * 3 {
+            allocator
+                .descriptor_set(&device, layout(1), &bindings, &buffers(&[buffer]))
+                .unwrap();
+        }
+
+        // 8 + 16 sets
+        assert_eq!(allocator.pools.len(), 2);
+        assert_eq!(allocator.next_pool_sets, INITIAL_POOL_SETS * 4);
+
+        let mut pools = device.pools.borrow().values().copied().collect::<Vec<_>>();
+        pools.sort();
+        assert_eq!(pools, [(8, 8), (16, 16)]);
+
+        allocator.clean(&device);
+
+        assert!(device.pools.borrow().is_empty());
+    }
+
+    #[test]
+    fn templates_are_shared_by_layout_and_bindings() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(true);
+        let bindings = [binding(0), binding(1)];
+
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+        allocator
+            .descriptor_set(&device, layout(1), &bindings, &buffers(&[12, 13]))
+            .unwrap();
+
+        assert_eq!(device.created_templates.get(), 1);
+
+        // fewer bindings of the same layout
+        allocator
+            .descriptor_set(&device, layout(1), &bindings[..1], &buffers(&[10]))
+            .unwrap();
+        allocator
+            .descriptor_set(&device, layout(2), &bindings, &buffers(&[10, 11]))
+            .unwrap();
+
+        assert_eq!(device.created_templates.get(), 3);
+
+        let writes = device.writes.borrow();
+        assert!(writes.iter().all(|(_, template)| template.is_some()));
+        assert_eq!(writes[0].1, writes[1].1);
+        assert_ne!(writes[0].1, writes[2].1);
+    }
+
+    #[test]
+    fn writes_without_templates() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(false);
+
+        allocator
+            .descriptor_set(&device, layout(1), &[binding(0)], &buffers(&[10]))
+            .unwrap();
+
+        assert_eq!(device.created_templates.get(), 0);
+        assert_eq!(device.writes.borrow()[0].1, None);
+    }
+
+    #[test]
+    fn rejects_buffers_not_matching_the_bindings() {
+        let device = FakeDevice::default();
+        let mut allocator = DescriptorAllocator::new(false);
+
+        assert!(allocator
+            .descriptor_set(&device, layout(1), &[binding(0)], &buffers(&[10, 11]))
+            .is_err());
+        assert_eq!(device.allocated_sets.get(), 0);
+    }
+}
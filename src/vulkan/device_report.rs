@@ -0,0 +1,486 @@
+use ash::vk;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+
+use super::CooperativeMatrixShape;
+
+#[derive(Serialize)]
+pub struct ComputeLimits {
+    pub max_compute_work_group_count: [u32; 3],
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    pub max_compute_shared_memory_size: u32,
+    pub max_push_constants_size: u32,
+    pub max_storage_buffer_range: u32,
+    pub timestamp_period: f32,
+}
+
+#[derive(Serialize)]
+pub struct MemoryHeapReport {
+    pub size: u64,
+    pub flags: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct MemoryTypeReport {
+    pub heap_index: u32,
+    pub property_flags: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct QueueFamilyReport {
+    pub queue_count: u32,
+    pub flags: Vec<&'static str>,
+    pub timestamp_valid_bits: u32,
+}
+
+// what the kernels can use beyond the limits
+#[derive(Serialize)]
+pub struct KernelSupport {
+    pub subgroup_size: u32,
+    pub subgroup_operations: Vec<&'static str>,
+    // min and max, None without subgroup size control
+    pub subgroup_size_range: Option<[u32; 2]>,
+    pub cooperative_matrix_shapes: Vec<CooperativeMatrixShape>,
+    pub shader_sm_count: Option<u32>,
+    pub calibrated_timestamps: bool,
+    pub performance_query: bool,
+}
+
+// everything the kernels depend on for one physical device
+#[derive(Serialize)]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: &'static str,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: String,
+    pub driver_version: String,
+    pub driver_name: String,
+    pub driver_info: String,
+    pub compute_limits: ComputeLimits,
+    pub memory_heaps: Vec<MemoryHeapReport>,
+    pub memory_types: Vec<MemoryTypeReport>,
+    pub queue_families: Vec<QueueFamilyReport>,
+    pub features: BTreeMap<&'static str, bool>,
+    pub kernel_support: KernelSupport,
+    pub extensions: Vec<String>,
+}
+
+fn version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+// NVIDIA packs the driver version differently, other vendors use the API version layout
+fn driver_version_string(vendor_id: u32, driver_version: u32) -> String {
+    match vendor_id {
+        0x10de => format!(
+            "{}.{}.{}.{}",
+            driver_version >> 22,
+            (driver_version >> 14) & 0xff,
+            (driver_version >> 6) & 0xff,
+            driver_version & 0x3f
+        ),
+        _ => version_string(driver_version),
+    }
+}
+
+fn device_type_name(device_type: vk::PhysicalDeviceType) -> &'static str {
+    match device_type {
+        vk::PhysicalDeviceType::INTEGRATED_GPU => "integrated GPU",
+        vk::PhysicalDeviceType::DISCRETE_GPU => "discrete GPU",
+        vk::PhysicalDeviceType::VIRTUAL_GPU => "virtual GPU",
+        vk::PhysicalDeviceType::CPU => "CPU",
+        _ => "other",
+    }
+}
+
+fn memory_heap_flag_names(flags: vk::MemoryHeapFlags) -> Vec<&'static str> {
+    let names = [
+        (vk::MemoryHeapFlags::DEVICE_LOCAL, "device local"),
+        (vk::MemoryHeapFlags::MULTI_INSTANCE, "multi instance"),
+    ];
+
+    names
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn memory_property_flag_names(flags: vk::MemoryPropertyFlags) -> Vec<&'static str> {
+    let names = [
+        (vk::MemoryPropertyFlags::DEVICE_LOCAL, "device local"),
+        (vk::MemoryPropertyFlags::HOST_VISIBLE, "host visible"),
+        (vk::MemoryPropertyFlags::HOST_COHERENT, "host coherent"),
+        (vk::MemoryPropertyFlags::HOST_CACHED, "host cached"),
+        (
+            vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            "lazily allocated",
+        ),
+        (vk::MemoryPropertyFlags::PROTECTED, "protected"),
+    ];
+
+    names
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn queue_flag_names(flags: vk::QueueFlags) -> Vec<&'static str> {
+    let names = [
+        (vk::QueueFlags::GRAPHICS, "graphics"),
+        (vk::QueueFlags::COMPUTE, "compute"),
+        (vk::QueueFlags::TRANSFER, "transfer"),
+        (vk::QueueFlags::SPARSE_BINDING, "sparse binding"),
+        (vk::QueueFlags::PROTECTED, "protected"),
+    ];
+
+    names
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+// the feature bits the kernels, the profiler and the debugging tools rely on
+fn get_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+) -> BTreeMap<&'static str, bool> {
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder().build();
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder().build();
+    let mut shader_clock_features = vk::PhysicalDeviceShaderClockFeaturesKHR::builder().build();
+
+    let supports_vulkan_12 = api_version >= vk::make_api_version(0, 1, 2, 0);
+    let supports_vulkan_13 = api_version >= vk::make_api_version(0, 1, 3, 0);
+    let supports_shader_clock = super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::KhrShaderClockFn::name(),
+    );
+
+    // structures are only chained when the device knows them
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder();
+
+    if supports_vulkan_12 {
+        features2 = features2.push_next(&mut vulkan_12_features);
+    }
+
+    if supports_vulkan_13 {
+        features2 = features2.push_next(&mut vulkan_13_features);
+    }
+
+    if supports_shader_clock {
+        features2 = features2.push_next(&mut shader_clock_features);
+    }
+
+    let mut features2 = features2.build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    let features = features2.features;
+
+    let mut feature_bits = BTreeMap::from([
+        ("shaderInt16", features.shader_int16),
+        ("shaderInt64", features.shader_int64),
+        ("shaderFloat64", features.shader_float64),
+        (
+            "pipelineStatisticsQuery",
+            features.pipeline_statistics_query,
+        ),
+        (
+            "vertexPipelineStoresAndAtomics",
+            features.vertex_pipeline_stores_and_atomics,
+        ),
+        (
+            "fragmentStoresAndAtomics",
+            features.fragment_stores_and_atomics,
+        ),
+    ]);
+
+    if supports_vulkan_12 {
+        feature_bits.insert("shaderFloat16", vulkan_12_features.shader_float16);
+        feature_bits.insert("shaderInt8", vulkan_12_features.shader_int8);
+        feature_bits.insert(
+            "storageBuffer8BitAccess",
+            vulkan_12_features.storage_buffer8_bit_access,
+        );
+        feature_bits.insert("hostQueryReset", vulkan_12_features.host_query_reset);
+        feature_bits.insert("timelineSemaphore", vulkan_12_features.timeline_semaphore);
+        feature_bits.insert(
+            "bufferDeviceAddress",
+            vulkan_12_features.buffer_device_address,
+        );
+        feature_bits.insert("vulkanMemoryModel", vulkan_12_features.vulkan_memory_model);
+    }
+
+    if supports_shader_clock {
+        feature_bits.insert(
+            "shaderDeviceClock",
+            shader_clock_features.shader_device_clock,
+        );
+        feature_bits.insert(
+            "shaderSubgroupClock",
+            shader_clock_features.shader_subgroup_clock,
+        );
+    }
+
+    if supports_vulkan_13 {
+        feature_bits.insert(
+            "subgroupSizeControl",
+            vulkan_13_features.subgroup_size_control,
+        );
+        feature_bits.insert(
+            "computeFullSubgroups",
+            vulkan_13_features.compute_full_subgroups,
+        );
+        feature_bits.insert("synchronization2", vulkan_13_features.synchronization2);
+        feature_bits.insert("maintenance4", vulkan_13_features.maintenance4);
+    }
+
+    feature_bits
+        .into_iter()
+        .map(|(name, supported)| (name, supported != 0))
+        .collect()
+}
+
+fn get_device_report(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Result<DeviceReport, String> {
+    let properties = super::get_physical_device_properties(instance, physical_device);
+
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    let queue_family_properties =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    let mut extensions = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .map_err(|_| String::from("failed to enumerate device extension properties"))?
+    }
+    .iter()
+    .map(|extension| super::c_str_to_string(&extension.extension_name))
+    .collect::<Vec<_>>();
+
+    extensions.sort();
+
+    // core in 1.2, the name and info stay empty without it
+    let mut driver_properties = vk::PhysicalDeviceDriverProperties::builder().build();
+
+    if properties.api_version >= vk::make_api_version(0, 1, 2, 0)
+        || super::is_device_extension_supported(
+            instance,
+            physical_device,
+            vk::KhrDriverPropertiesFn::name(),
+        )
+    {
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::builder().push_next(&mut driver_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+    }
+
+    let capabilities =
+        super::get_device_capabilities(entry, instance, physical_device, &properties);
+
+    let limits = &properties.limits;
+
+    Ok(DeviceReport {
+        name: super::c_str_to_string(&properties.device_name),
+        device_type: device_type_name(properties.device_type),
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        api_version: version_string(properties.api_version),
+        driver_version: driver_version_string(properties.vendor_id, properties.driver_version),
+        driver_name: super::c_str_to_string(&driver_properties.driver_name),
+        driver_info: super::c_str_to_string(&driver_properties.driver_info),
+        compute_limits: ComputeLimits {
+            max_compute_work_group_count: limits.max_compute_work_group_count,
+            max_compute_work_group_size: limits.max_compute_work_group_size,
+            max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+            max_compute_shared_memory_size: limits.max_compute_shared_memory_size,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_storage_buffer_range: limits.max_storage_buffer_range,
+            timestamp_period: limits.timestamp_period,
+        },
+        memory_heaps: memory_properties.memory_heaps
+            [..memory_properties.memory_heap_count as usize]
+            .iter()
+            .map(|heap| MemoryHeapReport {
+                size: heap.size,
+                flags: memory_heap_flag_names(heap.flags),
+            })
+            .collect(),
+        memory_types: memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .map(|memory_type| MemoryTypeReport {
+                heap_index: memory_type.heap_index,
+                property_flags: memory_property_flag_names(memory_type.property_flags),
+            })
+            .collect(),
+        queue_families: queue_family_properties
+            .iter()
+            .map(|queue_family| QueueFamilyReport {
+                queue_count: queue_family.queue_count,
+                flags: queue_flag_names(queue_family.queue_flags),
+                timestamp_valid_bits: queue_family.timestamp_valid_bits,
+            })
+            .collect(),
+        features: get_features(instance, physical_device, properties.api_version),
+        kernel_support: KernelSupport {
+            subgroup_size: capabilities.subgroup_size,
+            subgroup_operations: super::subgroup_operation_names(
+                capabilities.subgroup_supported_operations,
+            ),
+            subgroup_size_range: capabilities
+                .subgroup_size_control
+                .as_ref()
+                .map(|control| [control.min_subgroup_size, control.max_subgroup_size]),
+            cooperative_matrix_shapes: capabilities.cooperative_matrix_shapes,
+            shader_sm_count: capabilities.shader_sm_count,
+            calibrated_timestamps: capabilities
+                .calibrated_time_domains
+                .contains(&vk::TimeDomainEXT::DEVICE),
+            performance_query: capabilities.performance_query,
+        },
+        extensions,
+    })
+}
+
+// creates its own instance, so it works without a device suitable for the kernels
+pub fn get_device_reports() -> Result<Vec<DeviceReport>, String> {
+    log::info!("creating device reports");
+
+    let entry = super::create_entry();
+    super::check_instance_version(&entry)?;
+
+    let instance = super::create_instance(&entry, &Vec::new(), &Vec::new(), &[])?;
+
+    let reports = match unsafe { instance.enumerate_physical_devices() } {
+        Ok(physical_devices) => physical_devices
+            .into_iter()
+            .map(|physical_device| get_device_report(&entry, &instance, physical_device))
+            .collect::<Result<Vec<_>, String>>(),
+        Err(_) => Err(String::from("failed to enumerate physical devices")),
+    };
+
+    unsafe {
+        instance.destroy_instance(None);
+    }
+
+    reports
+}
+
+impl std::fmt::Display for DeviceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} ({}, vendor 0x{:04x}, device 0x{:04x})",
+            self.name, self.device_type, self.vendor_id, self.device_id
+        )?;
+        writeln!(f, "  api version {}", self.api_version)?;
+        writeln!(
+            f,
+            "  driver {} {} ({})",
+            self.driver_name, self.driver_version, self.driver_info
+        )?;
+
+        let limits = &self.compute_limits;
+
+        writeln!(f, "  compute limits")?;
+        writeln!(
+            f,
+            "    work group count {:?}, size {:?}, invocations {}",
+            limits.max_compute_work_group_count,
+            limits.max_compute_work_group_size,
+            limits.max_compute_work_group_invocations
+        )?;
+        writeln!(
+            f,
+            "    shared memory {} bytes, push constants {} bytes, storage buffer range {} bytes",
+            limits.max_compute_shared_memory_size,
+            limits.max_push_constants_size,
+            limits.max_storage_buffer_range
+        )?;
+        writeln!(f, "    timestamp period {} ns", limits.timestamp_period)?;
+
+        writeln!(f, "  memory heaps")?;
+        for (index, heap) in self.memory_heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: {:.1} MiB {:?}",
+                index,
+                heap.size as f64 / (1024.0 * 1024.0),
+                heap.flags
+            )?;
+        }
+
+        writeln!(f, "  memory types")?;
+        for (index, memory_type) in self.memory_types.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: heap {} {:?}",
+                index, memory_type.heap_index, memory_type.property_flags
+            )?;
+        }
+
+        writeln!(f, "  queue families")?;
+        for (index, queue_family) in self.queue_families.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: {} queues {:?}, timestamp valid bits {}",
+                index,
+                queue_family.queue_count,
+                queue_family.flags,
+                queue_family.timestamp_valid_bits
+            )?;
+        }
+
+        writeln!(f, "  features")?;
+        for (name, supported) in &self.features {
+            writeln!(f, "    {:<32} {}", name, supported)?;
+        }
+
+        let support = &self.kernel_support;
+
+        writeln!(f, "  kernel support")?;
+        writeln!(
+            f,
+            "    subgroup size {}, range {:?}, operations {:?}",
+            support.subgroup_size, support.subgroup_size_range, support.subgroup_operations
+        )?;
+        writeln!(
+            f,
+            "    cooperative matrix shapes {:?}",
+            support
+                .cooperative_matrix_shapes
+                .iter()
+                .map(|shape| format!("{}x{}x{}", shape.m, shape.n, shape.k))
+                .collect::<Vec<_>>()
+        )?;
+        writeln!(
+            f,
+            "    shader SM count {:?}, calibrated timestamps {}, performance queries {}",
+            support.shader_sm_count, support.calibrated_timestamps, support.performance_query
+        )?;
+
+        writeln!(f, "  extensions ({})", self.extensions.len())?;
+        for extension in &self.extensions {
+            writeln!(f, "    {}", extension)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+use ash::vk;
+
+use crate::constants;
+use crate::shader_interface::{PushConst, ShaderBinding};
+use crate::shader_variants::ShaderVariant;
+
+use super::{
+    ClockProfile, KernelConfig, MemBuffer, PerformanceCounterValue, SubmissionTiming, VulkanData,
+};
+
+// the buffers bound as the matrices A, B and C
+#[derive(Clone, Copy)]
+pub struct MatrixBuffers<'a> {
+    pub a: &'a MemBuffer,
+    pub b: &'a MemBuffer,
+    pub c: &'a MemBuffer,
+}
+
+impl MatrixBuffers<'_> {
+    // the buffers in the order of `shader_bindings`, the workgroup clocks buffer of the
+    // profiling variants included
+    pub fn bound_buffers(
+        &self,
+        shader_bindings: &[ShaderBinding],
+        workgroup_clocks: Option<&MemBuffer>,
+    ) -> Result<Vec<vk::Buffer>, String> {
+        shader_bindings
+            .iter()
+            .map(|shader_binding| match shader_binding.name {
+                "A" => Ok(self.a.buffer),
+                "B" => Ok(self.b.buffer),
+                "C" => Ok(self.c.buffer),
+                super::WORKGROUP_CLOCKS_BINDING_NAME => workgroup_clocks
+                    .map(|mem_buffer| mem_buffer.buffer)
+                    .ok_or_else(|| String::from("the workgroup clocks buffer is not bound")),
+                name => Err(format!("no buffer for the binding {}", name)),
+            })
+            .collect()
+    }
+}
+
+// what a dispatch produced besides the result buffer
+pub struct DispatchReport {
+    // GPU time in ms
+    pub gpu_time: f32,
+    // debugPrintfEXT output of the kernel, empty unless the debug printf mode is enabled
+    pub printf_messages: Vec<String>,
+    // per-workgroup clocks, only recorded by the profiling variants
+    pub clock_profile: Option<ClockProfile>,
+    // latencies around the dispatch on the host timeline, needs calibrated timestamps
+    pub submission_timing: Option<SubmissionTiming>,
+    // query results of the dispatch, collected when the device supports them
+    pub statistics: DispatchStatistics,
+}
+
+#[derive(Debug, Default)]
+pub struct DispatchStatistics {
+    // None without pipeline statistics queries
+    pub compute_shader_invocations: Option<u64>,
+    // empty unless performance counters are enabled
+    pub counters: Vec<PerformanceCounterValue>,
+}
+
+impl std::fmt::Display for DispatchStatistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.compute_shader_invocations {
+            Some(invocations) => writeln!(f, "compute shader invocations: {}", invocations)?,
+            None => writeln!(f, "compute shader invocations were not counted")?,
+        }
+
+        for counter in &self.counters {
+            writeln!(
+                f,
+                "  {:<24} {:<40} {:.3} {}",
+                counter.category, counter.name, counter.value, counter.unit
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// records, submits and waits for one multiplication of `buffers`; the pipeline is created from
+// `shader_variant` with the layout of vulkan_data
+pub fn dispatch(
+    vulkan_data: &VulkanData,
+    shader_variant: ShaderVariant,
+    pipeline: vk::Pipeline,
+    kernel_config: &KernelConfig,
+    buffers: MatrixBuffers,
+) -> Result<DispatchReport, String> {
+    kernel_config.check_limits(
+        shader_variant,
+        &vulkan_data.device_capabilities,
+        constants::N,
+    )?;
+
+    // messages printed by earlier work are not this dispatch's
+    if let Some(debug_messenger) = &vulkan_data.debug_messenger {
+        debug_messenger.take_printf_messages();
+    }
+
+    let [group_count_x, group_count_y, group_count_z] = kernel_config.group_count(constants::N);
+
+    let workgroup_count = (group_count_x * group_count_y * group_count_z) as usize;
+
+    // profiling variants write one entry per workgroup
+    let workgroup_clocks = match shader_variant
+        .bindings()
+        .iter()
+        .find(|b| b.name == super::WORKGROUP_CLOCKS_BINDING_NAME)
+    {
+        Some(binding) => Some((
+            binding.binding,
+            super::create_workgroup_clocks_buffer(vulkan_data, workgroup_count)?,
+        )),
+        None => None,
+    };
+
+    let report = bind_and_dispatch(
+        vulkan_data,
+        shader_variant,
+        pipeline,
+        [group_count_x, group_count_y, group_count_z],
+        buffers,
+        workgroup_clocks.as_ref().map(|(_, mem_buffer)| mem_buffer),
+    );
+
+    // the clocks buffer lives for one dispatch, whether it succeeded or not
+    if let Some((_, mem_buffer)) = &workgroup_clocks {
+        vulkan_data
+            .descriptor_allocator
+            .borrow_mut()
+            .forget_buffer(&vulkan_data.device, mem_buffer.buffer);
+
+        unsafe {
+            vulkan_data.device.destroy_buffer(mem_buffer.buffer, None);
+            vulkan_data
+                .device
+                .free_memory(mem_buffer.device_memory, None);
+        }
+    }
+
+    report
+}
+
+fn bind_and_dispatch(
+    vulkan_data: &VulkanData,
+    shader_variant: ShaderVariant,
+    pipeline: vk::Pipeline,
+    group_count: [u32; 3],
+    buffers: MatrixBuffers,
+    workgroup_clocks: Option<&MemBuffer>,
+) -> Result<DispatchReport, String> {
+    let bound_buffers = buffers.bound_buffers(shader_variant.bindings(), workgroup_clocks)?;
+
+    let descriptor_set = vulkan_data
+        .descriptor_allocator
+        .borrow_mut()
+        .descriptor_set(
+            &vulkan_data.device,
+            vulkan_data.descriptor_set_layout,
+            shader_variant.bindings(),
+            &bound_buffers,
+        )?;
+
+    let command_buffer = super::allocate_command_buffer(vulkan_data)?;
+
+    let report = record_and_wait(
+        vulkan_data,
+        pipeline,
+        group_count,
+        descriptor_set,
+        command_buffer,
+        buffers,
+        workgroup_clocks,
+    );
+
+    // released on every path, a failed candidate must not starve the dispatches after it
+    let reset = unsafe {
+        // the work of a failed dispatch may still be in flight
+        if report.is_err() {
+            let _ = vulkan_data.device.device_wait_idle();
+            vulkan_data.profiler.borrow_mut().discard_gpu_scopes();
+        }
+
+        // free command buffer
+        vulkan_data
+            .device
+            .free_command_buffers(vulkan_data.command_pool, &[command_buffer]);
+
+        // reset command pool
+        vulkan_data
+            .device
+            .reset_command_pool(
+                vulkan_data.command_pool,
+                vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+            )
+            .map_err(|_| String::from("failed to reset command pool"))
+    };
+
+    let report = report?;
+    reset?;
+
+    Ok(report)
+}
+
+fn record_and_wait(
+    vulkan_data: &VulkanData,
+    pipeline: vk::Pipeline,
+    group_count: [u32; 3],
+    descriptor_set: vk::DescriptorSet,
+    command_buffer: vk::CommandBuffer,
+    buffers: MatrixBuffers,
+    workgroup_clocks: Option<&MemBuffer>,
+) -> Result<DispatchReport, String> {
+    let [group_count_x, group_count_y, group_count_z] = group_count;
+
+    let workgroup_count = (group_count_x * group_count_y * group_count_z) as usize;
+
+    super::begin_command_buffer(vulkan_data, command_buffer)?;
+
+    let push_const = PushConst {
+        N: constants::N as u32,
+    };
+
+    unsafe {
+        vulkan_data.device.cmd_push_constants(
+            command_buffer,
+            vulkan_data.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_const.as_bytes(),
+        );
+
+        vulkan_data.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            vulkan_data.pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+
+        vulkan_data.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline,
+        );
+
+        if let Some(statistics_query_pool) = vulkan_data.statistics_query_pool {
+            vulkan_data
+                .device
+                .cmd_reset_query_pool(command_buffer, statistics_query_pool, 0, 1);
+
+            vulkan_data.device.cmd_begin_query(
+                command_buffer,
+                statistics_query_pool,
+                0,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+
+        if let Some(performance_counters) = &vulkan_data.performance_counters {
+            performance_counters.begin(&vulkan_data.device, command_buffer);
+        }
+
+        let scope_id = vulkan_data.profiler.borrow_mut().begin_gpu_scope(
+            &vulkan_data.device,
+            command_buffer,
+            "dispatch",
+        )?;
+
+        vulkan_data.device.cmd_dispatch(
+            command_buffer,
+            group_count_x,
+            group_count_y,
+            group_count_z,
+        );
+
+        vulkan_data.profiler.borrow_mut().end_gpu_scope(
+            &vulkan_data.device,
+            command_buffer,
+            scope_id,
+        );
+
+        if let Some(performance_counters) = &vulkan_data.performance_counters {
+            performance_counters.end(&vulkan_data.device, command_buffer);
+        }
+
+        if let Some(statistics_query_pool) = vulkan_data.statistics_query_pool {
+            vulkan_data
+                .device
+                .cmd_end_query(command_buffer, statistics_query_pool, 0);
+        }
+
+        // host mapped results and workgroup clocks are read without a copy that would
+        // otherwise order the access
+        if buffers.c.host_mapped() || workgroup_clocks.is_some() {
+            let compute_to_host = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+
+            vulkan_data.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[compute_to_host],
+                &[],
+                &[],
+            );
+        }
+
+        vulkan_data
+            .device
+            .end_command_buffer(command_buffer)
+            .map_err(|_| String::from("failed to end command buffer"))?
+    }
+
+    let submission = super::submit(vulkan_data, command_buffer)?;
+
+    unsafe {
+        // wait until the GPU is done with all work
+        vulkan_data
+            .device
+            .device_wait_idle()
+            .map_err(|_| String::from("failed to wait device idle"))?;
+
+        let gpu_spans = vulkan_data
+            .profiler
+            .borrow_mut()
+            .resolve_gpu_scopes(&vulkan_data.device, &submission)?;
+
+        let submission_timing = vulkan_data.profiler.borrow().last_submission_timing();
+
+        let compute_shader_invocations = match vulkan_data.statistics_query_pool {
+            Some(statistics_query_pool) => {
+                let mut query_data = [0u64; 1];
+
+                vulkan_data
+                    .device
+                    .get_query_pool_results(
+                        statistics_query_pool,
+                        0,
+                        1,
+                        &mut query_data,
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                    .map_err(|_| String::from("failed to get pipeline statistics"))?;
+
+                Some(query_data[0])
+            }
+            None => None,
+        };
+
+        let counters = match &vulkan_data.performance_counters {
+            Some(performance_counters) => performance_counters.read(&vulkan_data.device)?,
+            None => Vec::new(),
+        };
+
+        let clock_profile = match workgroup_clocks {
+            Some(mem_buffer) => {
+                let clocks =
+                    super::read_workgroup_clocks(vulkan_data, mem_buffer, workgroup_count)?;
+
+                Some(ClockProfile::new(&clocks))
+            }
+            None => None,
+        };
+
+        // 0 when the queue cannot write timestamps
+        let gpu_time = gpu_spans.iter().map(|span| span.duration_ms()).sum::<f32>();
+
+        // the layer reports the printed messages once the queue is idle
+        let printf_messages = match &vulkan_data.debug_messenger {
+            Some(debug_messenger) => debug_messenger.take_printf_messages(),
+            None => Vec::new(),
+        };
+
+        Ok(DispatchReport {
+            gpu_time,
+            printf_messages,
+            clock_profile,
+            submission_timing,
+            statistics: DispatchStatistics {
+                compute_shader_invocations,
+                counters,
+            },
+        })
+    }
+}
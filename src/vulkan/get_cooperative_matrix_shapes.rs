@@ -0,0 +1,309 @@
+use ash::prelude::VkResult;
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+// M x N x K of one cooperative matrix multiply-add: an M x K by K x N product added to M x N
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CooperativeMatrixShape {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+}
+
+fn component_type_name(component_type: vk::ComponentTypeNV) -> String {
+    match component_type {
+        vk::ComponentTypeNV::FLOAT16 => String::from("f16"),
+        vk::ComponentTypeNV::FLOAT32 => String::from("f32"),
+        vk::ComponentTypeNV::FLOAT64 => String::from("f64"),
+        vk::ComponentTypeNV::SINT8 => String::from("i8"),
+        vk::ComponentTypeNV::SINT16 => String::from("i16"),
+        vk::ComponentTypeNV::SINT32 => String::from("i32"),
+        vk::ComponentTypeNV::SINT64 => String::from("i64"),
+        vk::ComponentTypeNV::UINT8 => String::from("u8"),
+        vk::ComponentTypeNV::UINT16 => String::from("u16"),
+        vk::ComponentTypeNV::UINT32 => String::from("u32"),
+        vk::ComponentTypeNV::UINT64 => String::from("u64"),
+        _ => format!("unknown({})", component_type.as_raw()),
+    }
+}
+
+// VK_KHR_cooperative_matrix, the ash version used here has no bindings for it; shaped like the
+// ash ones so they can replace it. its component types and scopes keep the values of the NV
+// extension
+const KHR_COOPERATIVE_MATRIX_NAME: &std::ffi::CStr = c"VK_KHR_cooperative_matrix";
+
+const COOPERATIVE_MATRIX_PROPERTIES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_506_001);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CooperativeMatrixPropertiesKHR {
+    s_type: vk::StructureType,
+    p_next: *mut std::ffi::c_void,
+    m_size: u32,
+    n_size: u32,
+    k_size: u32,
+    a_type: vk::ComponentTypeNV,
+    b_type: vk::ComponentTypeNV,
+    c_type: vk::ComponentTypeNV,
+    result_type: vk::ComponentTypeNV,
+    saturating_accumulation: vk::Bool32,
+    scope: vk::ScopeNV,
+}
+
+impl Default for CooperativeMatrixPropertiesKHR {
+    fn default() -> Self {
+        Self {
+            s_type: COOPERATIVE_MATRIX_PROPERTIES_KHR,
+            p_next: std::ptr::null_mut(),
+            m_size: 0,
+            n_size: 0,
+            k_size: 0,
+            a_type: vk::ComponentTypeNV::default(),
+            b_type: vk::ComponentTypeNV::default(),
+            c_type: vk::ComponentTypeNV::default(),
+            result_type: vk::ComponentTypeNV::default(),
+            saturating_accumulation: vk::FALSE,
+            scope: vk::ScopeNV::default(),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+type PFN_vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR =
+    unsafe extern "system" fn(
+        physical_device: vk::PhysicalDevice,
+        p_property_count: *mut u32,
+        p_properties: *mut CooperativeMatrixPropertiesKHR,
+    ) -> vk::Result;
+
+struct KhrCooperativeMatrix {
+    get_physical_device_cooperative_matrix_properties_khr:
+        PFN_vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR,
+}
+
+impl KhrCooperativeMatrix {
+    // None when the loader does not know the command
+    fn new(entry: &ash::Entry, instance: &ash::Instance) -> Option<Self> {
+        let name = c"vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR";
+
+        let f = unsafe { entry.get_instance_proc_addr(instance.handle(), name.as_ptr()) }?;
+
+        // the signature of the command queried by name, as the ash loaders do
+        let get_physical_device_cooperative_matrix_properties_khr = unsafe {
+            std::mem::transmute::<
+                unsafe extern "system" fn(),
+                PFN_vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR,
+            >(f)
+        };
+
+        Some(Self {
+            get_physical_device_cooperative_matrix_properties_khr,
+        })
+    }
+
+    // in the layout of the NV properties, which they extend by saturating accumulation
+    unsafe fn get_physical_device_cooperative_matrix_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> VkResult<Vec<vk::CooperativeMatrixPropertiesNV>> {
+        let mut count = 0;
+
+        (self.get_physical_device_cooperative_matrix_properties_khr)(
+            physical_device,
+            &mut count,
+            std::ptr::null_mut(),
+        )
+        .result()?;
+
+        let mut properties = vec![CooperativeMatrixPropertiesKHR::default(); count as usize];
+
+        let result = (self.get_physical_device_cooperative_matrix_properties_khr)(
+            physical_device,
+            &mut count,
+            properties.as_mut_ptr(),
+        );
+
+        if result != vk::Result::INCOMPLETE {
+            result.result()?;
+        }
+
+        properties.truncate(count as usize);
+
+        Ok(properties
+            .iter()
+            .map(|props| {
+                vk::CooperativeMatrixPropertiesNV::builder()
+                    .m_size(props.m_size)
+                    .n_size(props.n_size)
+                    .k_size(props.k_size)
+                    .a_type(props.a_type)
+                    .b_type(props.b_type)
+                    .c_type(props.c_type)
+                    .d_type(props.result_type)
+                    .scope(props.scope)
+                    .build()
+            })
+            .collect())
+    }
+}
+
+// f16 inputs accumulated in f32 by a subgroup
+fn kernel_shapes(properties: &[vk::CooperativeMatrixPropertiesNV]) -> Vec<CooperativeMatrixShape> {
+    let mut shapes = Vec::new();
+
+    for props in properties {
+        log::info!(
+            "cooperative matrix {}x{}x{}: A {} B {} C {} D {}, scope {}",
+            props.m_size,
+            props.n_size,
+            props.k_size,
+            component_type_name(props.a_type),
+            component_type_name(props.b_type),
+            component_type_name(props.c_type),
+            component_type_name(props.d_type),
+            props.scope.as_raw()
+        );
+
+        if props.a_type == vk::ComponentTypeNV::FLOAT16
+            && props.b_type == vk::ComponentTypeNV::FLOAT16
+            && props.c_type == vk::ComponentTypeNV::FLOAT32
+            && props.d_type == vk::ComponentTypeNV::FLOAT32
+            && props.scope == vk::ScopeNV::SUBGROUP
+        {
+            shapes.push(CooperativeMatrixShape {
+                m: props.m_size,
+                n: props.n_size,
+                k: props.k_size,
+            });
+        }
+    }
+
+    shapes
+}
+
+// the kernel is compiled for VK_NV_cooperative_matrix, the shaderc version used here predates
+// GL_KHR_cooperative_matrix; a device with only the KHR extension is reported but cannot run it
+fn report_khr_shapes(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) {
+    if !super::is_device_extension_supported(instance, physical_device, KHR_COOPERATIVE_MATRIX_NAME)
+    {
+        return;
+    }
+
+    let properties = KhrCooperativeMatrix::new(entry, instance)
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+        .and_then(|khr_cooperative_matrix| unsafe {
+            khr_cooperative_matrix
+                .get_physical_device_cooperative_matrix_properties(physical_device)
+        });
+
+    match properties {
+        Ok(properties) => log::warn!(
+            "the device supports {:?} with {} shapes for the kernel, which needs {:?}",
+            KHR_COOPERATIVE_MATRIX_NAME,
+            kernel_shapes(&properties).len(),
+            vk::NvCooperativeMatrixFn::name()
+        ),
+        Err(_) => log::warn!("failed to get {:?} properties", KHR_COOPERATIVE_MATRIX_NAME),
+    }
+}
+
+// shapes the cooperative matrix kernel can use; empty when VK_NV_cooperative_matrix or one of
+// the features it needs is missing
+pub fn get_cooperative_matrix_shapes(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Vec<CooperativeMatrixShape> {
+    log::info!("getting cooperative matrix shapes");
+
+    if !super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::NvCooperativeMatrixFn::name(),
+    ) {
+        log::info!(
+            "device extension {:?} is not supported",
+            vk::NvCooperativeMatrixFn::name()
+        );
+        report_khr_shapes(entry, instance, physical_device);
+        return Vec::new();
+    }
+
+    // features
+    let mut cooperative_matrix_features =
+        vk::PhysicalDeviceCooperativeMatrixFeaturesNV::builder().build();
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder().build();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut cooperative_matrix_features)
+        .push_next(&mut vulkan_12_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if cooperative_matrix_features.cooperative_matrix == 0 {
+        log::info!("the device does not support the cooperative matrix feature");
+        return Vec::new();
+    }
+
+    if vulkan_12_features.shader_float16 == 0 {
+        log::info!("the device does not support shader float16");
+        return Vec::new();
+    }
+
+    // properties
+    let mut cooperative_matrix_properties =
+        vk::PhysicalDeviceCooperativeMatrixPropertiesNV::builder().build();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::builder().push_next(&mut cooperative_matrix_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+    if !cooperative_matrix_properties
+        .cooperative_matrix_supported_stages
+        .contains(vk::ShaderStageFlags::COMPUTE)
+    {
+        log::info!("cooperative matrices are not supported in compute shaders");
+        return Vec::new();
+    }
+
+    // shapes and component types
+    let cooperative_matrix_fn = vk::NvCooperativeMatrixFn::load(|name| unsafe {
+        entry
+            .get_instance_proc_addr(instance.handle(), name.as_ptr())
+            .map_or(std::ptr::null(), |f| f as *const std::ffi::c_void)
+    });
+
+    let mut count = 0;
+    let result = unsafe {
+        (cooperative_matrix_fn.get_physical_device_cooperative_matrix_properties_nv)(
+            physical_device,
+            &mut count,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if result != vk::Result::SUCCESS {
+        log::warn!("failed to get cooperative matrix property count");
+        return Vec::new();
+    }
+
+    let mut properties = vec![vk::CooperativeMatrixPropertiesNV::default(); count as usize];
+    let result = unsafe {
+        (cooperative_matrix_fn.get_physical_device_cooperative_matrix_properties_nv)(
+            physical_device,
+            &mut count,
+            properties.as_mut_ptr(),
+        )
+    };
+
+    if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+        log::warn!("failed to get cooperative matrix properties");
+        return Vec::new();
+    }
+
+    properties.truncate(count as usize);
+
+    kernel_shapes(&properties)
+}
@@ -0,0 +1,401 @@
+use ash::vk;
+
+use crate::shader_variants::ShaderVariant;
+
+use super::CooperativeMatrixShape;
+
+// VK_EXT_subgroup_size_control, core since 1.3
+pub struct SubgroupSizeControl {
+    pub min_subgroup_size: u32,
+    pub max_subgroup_size: u32,
+    pub max_compute_workgroup_subgroups: u32,
+    pub compute_full_subgroups: bool,
+    // the extension has to be enabled on devices older than 1.3
+    pub extension_required: bool,
+}
+
+// what the kernels may rely on besides the core properties
+pub struct DeviceCapabilities {
+    pub limits: vk::PhysicalDeviceLimits,
+    pub subgroup_size: u32,
+    pub subgroup_supported_stages: vk::ShaderStageFlags,
+    pub subgroup_supported_operations: vk::SubgroupFeatureFlags,
+    pub subgroup_size_control: Option<SubgroupSizeControl>,
+    pub cooperative_matrix_shapes: Vec<CooperativeMatrixShape>,
+    // number of SMs, when VK_NV_shader_sm_builtins is supported
+    pub shader_sm_count: Option<u32>,
+    // time domains of VK_EXT_calibrated_timestamps, empty without it
+    pub calibrated_time_domains: Vec<vk::TimeDomainEXT>,
+    // compute shader invocations can be counted
+    pub pipeline_statistics_query: bool,
+    // VK_KHR_performance_query with the query pools and host resets it is used with
+    pub performance_query: bool,
+    // heap budgets and usage through VK_EXT_memory_budget
+    pub memory_budget: bool,
+    // a device local memory type the host can map, on integrated GPUs, with resizable BAR and
+    // on CPU implementations
+    pub host_visible_device_local: bool,
+    // minimum alignment of host pointers imported through VK_EXT_external_memory_host
+    pub imported_host_pointer_alignment: Option<vk::DeviceSize>,
+    // vkCmdPipelineBarrier2, only used on devices where synchronization2 is core
+    pub synchronization2: bool,
+    // descriptor sets written with one call through an update template, core in 1.1
+    pub descriptor_update_template: bool,
+}
+
+impl DeviceCapabilities {
+    pub fn supports_subgroup_operations(&self, operations: vk::SubgroupFeatureFlags) -> bool {
+        self.subgroup_supported_stages
+            .contains(vk::ShaderStageFlags::COMPUTE)
+            && self.subgroup_supported_operations.contains(operations)
+    }
+
+    // whether the device has what the kernel needs
+    pub fn check_shader_variant(&self, shader_variant: ShaderVariant) -> Result<(), String> {
+        match shader_variant {
+            ShaderVariant::Cooperative if self.cooperative_matrix_shapes.is_empty() => {
+                Err(String::from("cooperative matrices are not supported"))
+            }
+            ShaderVariant::SubgroupReduce
+                if !self.supports_subgroup_operations(vk::SubgroupFeatureFlags::ARITHMETIC) =>
+            {
+                Err(String::from(
+                    "subgroup arithmetic is not supported in compute shaders",
+                ))
+            }
+            ShaderVariant::SubgroupShuffle
+                if !self.supports_subgroup_operations(vk::SubgroupFeatureFlags::SHUFFLE) =>
+            {
+                Err(String::from(
+                    "subgroup shuffles are not supported in compute shaders",
+                ))
+            }
+            ShaderVariant::TiledClocksSm if self.shader_sm_count.is_none() => {
+                Err(String::from("shader SM builtins are not supported"))
+            }
+            ShaderVariant::StreamCopy | ShaderVariant::FmaLoop => Err(String::from(
+                "roofline micro-benchmarks do not multiply matrices",
+            )),
+            ShaderVariant::GemmPanel => Err(String::from(
+                "the out-of-core panel kernel only runs within multiply_out_of_core",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn subgroup_operation_names(operations: vk::SubgroupFeatureFlags) -> Vec<&'static str> {
+    let names = [
+        (vk::SubgroupFeatureFlags::BASIC, "basic"),
+        (vk::SubgroupFeatureFlags::VOTE, "vote"),
+        (vk::SubgroupFeatureFlags::ARITHMETIC, "arithmetic"),
+        (vk::SubgroupFeatureFlags::BALLOT, "ballot"),
+        (vk::SubgroupFeatureFlags::SHUFFLE, "shuffle"),
+        (
+            vk::SubgroupFeatureFlags::SHUFFLE_RELATIVE,
+            "shuffle relative",
+        ),
+        (vk::SubgroupFeatureFlags::CLUSTERED, "clustered"),
+        (vk::SubgroupFeatureFlags::QUAD, "quad"),
+        (vk::SubgroupFeatureFlags::PARTITIONED_NV, "partitioned"),
+    ];
+
+    names
+        .iter()
+        .filter(|(flag, _)| operations.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn get_subgroup_size_control(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: &vk::PhysicalDeviceProperties,
+) -> Option<SubgroupSizeControl> {
+    let core = vk::api_version_major(properties.api_version) > 1
+        || vk::api_version_minor(properties.api_version) >= 3;
+
+    let extension_required = !core;
+
+    if extension_required
+        && !super::is_device_extension_supported(
+            instance,
+            physical_device,
+            vk::ExtSubgroupSizeControlFn::name(),
+        )
+    {
+        return None;
+    }
+
+    let mut size_control_features =
+        vk::PhysicalDeviceSubgroupSizeControlFeatures::builder().build();
+    let mut features2 =
+        vk::PhysicalDeviceFeatures2::builder().push_next(&mut size_control_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if size_control_features.subgroup_size_control == 0 {
+        return None;
+    }
+
+    let mut size_control_properties =
+        vk::PhysicalDeviceSubgroupSizeControlProperties::builder().build();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::builder().push_next(&mut size_control_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+    if !size_control_properties
+        .required_subgroup_size_stages
+        .contains(vk::ShaderStageFlags::COMPUTE)
+    {
+        return None;
+    }
+
+    Some(SubgroupSizeControl {
+        min_subgroup_size: size_control_properties.min_subgroup_size,
+        max_subgroup_size: size_control_properties.max_subgroup_size,
+        max_compute_workgroup_subgroups: size_control_properties.max_compute_workgroup_subgroups,
+        compute_full_subgroups: size_control_features.compute_full_subgroups != 0,
+        extension_required,
+    })
+}
+
+fn get_shader_sm_count(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<u32> {
+    if !super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::NvShaderSmBuiltinsFn::name(),
+    ) {
+        return None;
+    }
+
+    let mut sm_builtins_features = vk::PhysicalDeviceShaderSMBuiltinsFeaturesNV::builder().build();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder().push_next(&mut sm_builtins_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if sm_builtins_features.shader_sm_builtins == 0 {
+        return None;
+    }
+
+    let mut sm_builtins_properties =
+        vk::PhysicalDeviceShaderSMBuiltinsPropertiesNV::builder().build();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::builder().push_next(&mut sm_builtins_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+    Some(sm_builtins_properties.shader_sm_count)
+}
+
+fn get_calibrated_time_domains(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Vec<vk::TimeDomainEXT> {
+    if !super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::ExtCalibratedTimestampsFn::name(),
+    ) {
+        return Vec::new();
+    }
+
+    let calibrated_timestamps = ash::extensions::ext::CalibratedTimestamps::new(entry, instance);
+
+    unsafe {
+        calibrated_timestamps
+            .get_physical_device_calibrateable_time_domains(physical_device)
+            .unwrap_or_default()
+    }
+}
+
+fn get_imported_host_pointer_alignment(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<vk::DeviceSize> {
+    if !super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::ExtExternalMemoryHostFn::name(),
+    ) {
+        return None;
+    }
+
+    let mut external_memory_host_properties =
+        vk::PhysicalDeviceExternalMemoryHostPropertiesEXT::builder().build();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::builder().push_next(&mut external_memory_host_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+    Some(external_memory_host_properties.min_imported_host_pointer_alignment)
+}
+
+fn get_synchronization2_support(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: &vk::PhysicalDeviceProperties,
+) -> bool {
+    let core = vk::api_version_major(properties.api_version) > 1
+        || vk::api_version_minor(properties.api_version) >= 3;
+
+    if !core {
+        return false;
+    }
+
+    let mut synchronization2_features =
+        vk::PhysicalDeviceSynchronization2Features::builder().build();
+    let mut features2 =
+        vk::PhysicalDeviceFeatures2::builder().push_next(&mut synchronization2_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    synchronization2_features.synchronization2 != 0
+}
+
+fn get_performance_query_support(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    if !super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::KhrPerformanceQueryFn::name(),
+    ) {
+        return false;
+    }
+
+    let mut performance_query_features =
+        vk::PhysicalDevicePerformanceQueryFeaturesKHR::builder().build();
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder().build();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut performance_query_features)
+        .push_next(&mut vulkan_12_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    performance_query_features.performance_counter_query_pools != 0
+        && vulkan_12_features.host_query_reset != 0
+}
+
+pub fn get_device_capabilities(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: &vk::PhysicalDeviceProperties,
+) -> DeviceCapabilities {
+    log::info!("getting device capabilities");
+
+    // subgroups
+    let mut subgroup_properties = vk::PhysicalDeviceSubgroupProperties::builder().build();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+    log::info!("subgroup size: {}", subgroup_properties.subgroup_size);
+    log::info!(
+        "subgroup operations: {:?}",
+        subgroup_operation_names(subgroup_properties.supported_operations)
+    );
+
+    let subgroup_size_control = get_subgroup_size_control(instance, physical_device, properties);
+
+    match &subgroup_size_control {
+        Some(size_control) => log::info!(
+            "subgroup size control: {}..={}, full subgroups: {}",
+            size_control.min_subgroup_size,
+            size_control.max_subgroup_size,
+            size_control.compute_full_subgroups
+        ),
+        None => log::info!("subgroup size control is not supported"),
+    }
+
+    let shader_sm_count = get_shader_sm_count(instance, physical_device);
+
+    match shader_sm_count {
+        Some(sm_count) => log::info!("shader SM count: {}", sm_count),
+        None => log::info!("shader SM builtins are not supported"),
+    }
+
+    let calibrated_time_domains = get_calibrated_time_domains(entry, instance, physical_device);
+
+    log::info!(
+        "calibrated time domains: {:?}",
+        calibrated_time_domains
+            .iter()
+            .map(|domain| domain.as_raw())
+            .collect::<Vec<_>>()
+    );
+
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+
+    let performance_query = get_performance_query_support(instance, physical_device);
+
+    log::info!(
+        "pipeline statistics queries: {}, performance queries: {}",
+        features.pipeline_statistics_query != 0,
+        performance_query
+    );
+
+    let memory_budget = super::is_device_extension_supported(
+        instance,
+        physical_device,
+        vk::ExtMemoryBudgetFn::name(),
+    );
+
+    log::info!("memory budget: {}", memory_budget);
+
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    let host_visible_device_local = memory_properties.memory_types
+        [..memory_properties.memory_type_count as usize]
+        .iter()
+        .any(|memory_type| {
+            memory_type
+                .property_flags
+                .contains(super::HOST_VISIBLE_DEVICE_LOCAL)
+        });
+
+    log::info!(
+        "host visible device local memory: {}",
+        host_visible_device_local
+    );
+
+    let imported_host_pointer_alignment =
+        get_imported_host_pointer_alignment(instance, physical_device);
+
+    match imported_host_pointer_alignment {
+        Some(alignment) => log::info!("imported host pointer alignment: {}", alignment),
+        None => log::info!("host pointers cannot be imported"),
+    }
+
+    let synchronization2 = get_synchronization2_support(instance, physical_device, properties);
+
+    log::info!("synchronization2: {}", synchronization2);
+
+    let descriptor_update_template = vk::api_version_major(properties.api_version) > 1
+        || vk::api_version_minor(properties.api_version) >= 1;
+
+    log::info!("descriptor update template: {}", descriptor_update_template);
+
+    DeviceCapabilities {
+        limits: properties.limits,
+        subgroup_size: subgroup_properties.subgroup_size,
+        subgroup_supported_stages: subgroup_properties.supported_stages,
+        subgroup_supported_operations: subgroup_properties.supported_operations,
+        subgroup_size_control,
+        cooperative_matrix_shapes: super::get_cooperative_matrix_shapes(
+            entry,
+            instance,
+            physical_device,
+        ),
+        shader_sm_count,
+        calibrated_time_domains,
+        pipeline_statistics_query: features.pipeline_statistics_query != 0,
+        performance_query,
+        memory_budget,
+        host_visible_device_local,
+        imported_host_pointer_alignment,
+        synchronization2,
+        descriptor_update_template,
+    }
+}
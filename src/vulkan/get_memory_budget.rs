@@ -0,0 +1,167 @@
+use ash::vk;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub size: vk::DeviceSize,
+    // what the process may allocate from the heap, the heap size without VK_EXT_memory_budget
+    pub budget: vk::DeviceSize,
+    // allocated by the process, None without VK_EXT_memory_budget
+    pub usage: Option<vk::DeviceSize>,
+    pub device_local: bool,
+}
+
+impl HeapBudget {
+    pub fn available(&self) -> vk::DeviceSize {
+        self.budget.saturating_sub(self.usage.unwrap_or(0))
+    }
+}
+
+// a snapshot of the heap budgets, it changes as this and other processes allocate
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    pub heaps: Vec<HeapBudget>,
+    // whether the budgets and usage come from VK_EXT_memory_budget
+    pub reported: bool,
+    // heap of every memory type
+    memory_type_heaps: Vec<u32>,
+}
+
+impl MemoryBudget {
+    // fails with what the heap holds when the allocation would exceed its budget
+    pub fn check_allocation(
+        &self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<(), String> {
+        let heap_index = self.memory_type_heaps[memory_type_index as usize];
+        let heap = &self.heaps[heap_index as usize];
+
+        if size <= heap.available() {
+            return Ok(());
+        }
+
+        Err(format!(
+            "allocating {:.1} MiB would exceed the budget of memory heap {}: {:.1} MiB of {:.1} MiB in use",
+            size as f64 / MIB,
+            heap_index,
+            heap.usage.unwrap_or(0) as f64 / MIB,
+            heap.budget as f64 / MIB
+        ))
+    }
+
+    // how many jobs needing `job_size` bytes of device local memory each fit in the largest
+    // device local heap right now
+    pub fn concurrent_jobs(&self, job_size: vk::DeviceSize) -> u64 {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.device_local)
+            .map(|heap| heap.available() / job_size.max(1))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+impl MemoryBudget {
+    // one device local heap with `available` bytes left, for tests of the budget users
+    pub fn device_local(available: vk::DeviceSize) -> Self {
+        Self {
+            heaps: vec![HeapBudget {
+                size: available,
+                budget: available,
+                usage: None,
+                device_local: true,
+            }],
+            reported: false,
+            memory_type_heaps: vec![0],
+        }
+    }
+}
+
+impl std::fmt::Display for MemoryBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reported {
+            true => writeln!(f, "memory budget reported by VK_EXT_memory_budget")?,
+            false => writeln!(f, "memory budget limited to the heap sizes")?,
+        }
+
+        for (index, heap) in self.heaps.iter().enumerate() {
+            let usage = match heap.usage {
+                Some(usage) => format!("{:.1} MiB", usage as f64 / MIB),
+                None => String::from("unknown"),
+            };
+
+            writeln!(
+                f,
+                "heap {}{}: {} used, budget {:.1} MiB of {:.1} MiB",
+                index,
+                match heap.device_local {
+                    true => " (device local)",
+                    false => "",
+                },
+                usage,
+                heap.budget as f64 / MIB,
+                heap.size as f64 / MIB
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn get_memory_budget(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    memory_budget_supported: bool,
+) -> MemoryBudget {
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::builder().build();
+
+    let mut memory_properties2 = vk::PhysicalDeviceMemoryProperties2::builder();
+
+    if memory_budget_supported {
+        memory_properties2 = memory_properties2.push_next(&mut budget_properties);
+    }
+
+    let mut memory_properties2 = memory_properties2.build();
+
+    unsafe {
+        instance.get_physical_device_memory_properties2(physical_device, &mut memory_properties2)
+    };
+
+    let memory_properties = memory_properties2.memory_properties;
+
+    let heaps = (0..memory_properties.memory_heap_count as usize)
+        .map(|index| {
+            let heap = memory_properties.memory_heaps[index];
+
+            let (budget, usage) = match memory_budget_supported {
+                true => (
+                    budget_properties.heap_budget[index],
+                    Some(budget_properties.heap_usage[index]),
+                ),
+                false => (heap.size, None),
+            };
+
+            HeapBudget {
+                size: heap.size,
+                budget,
+                usage,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            }
+        })
+        .collect();
+
+    let memory_type_heaps = memory_properties.memory_types
+        [..memory_properties.memory_type_count as usize]
+        .iter()
+        .map(|memory_type| memory_type.heap_index)
+        .collect();
+
+    MemoryBudget {
+        heaps,
+        reported: memory_budget_supported,
+        memory_type_heaps,
+    }
+}
@@ -0,0 +1,151 @@
+use ash::vk;
+
+use crate::constants;
+
+use super::{DispatchReport, MatrixBuffers, MemBuffer, Pod, VulkanData};
+
+// a row major matrix that stays in device memory between operations, so chained operations do
+// not round trip through the host; release it with `clean`
+pub struct GpuMatrix<T: Pod> {
+    pub mem_buffer: MemBuffer,
+    pub rows: usize,
+    pub cols: usize,
+    element_type: std::marker::PhantomData<T>,
+}
+
+impl<T: Pod> GpuMatrix<T> {
+    // uninitialized, to be written by an upload or an operation
+    pub fn new(vulkan_data: &VulkanData, rows: usize, cols: usize) -> Result<Self, String> {
+        log::info!("creating {} x {} gpu matrix", rows, cols);
+
+        if rows == 0 || cols == 0 {
+            return Err(String::from("a gpu matrix needs at least one element"));
+        }
+
+        let size = (rows * cols * std::mem::size_of::<T>()) as vk::DeviceSize;
+
+        let mem_buffer = super::create_matrix_mem_buffer(
+            &vulkan_data.instance,
+            vulkan_data.physical_device,
+            &vulkan_data.device,
+            &vulkan_data.device_capabilities,
+            size,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+
+        Ok(Self {
+            mem_buffer,
+            rows,
+            cols,
+            element_type: std::marker::PhantomData,
+        })
+    }
+
+    pub fn upload(
+        vulkan_data: &VulkanData,
+        rows: usize,
+        cols: usize,
+        data: &[T],
+    ) -> Result<Self, String> {
+        if data.len() != rows * cols {
+            return Err(format!(
+                "{} elements do not make a {} x {} matrix",
+                data.len(),
+                rows,
+                cols
+            ));
+        }
+
+        let matrix = Self::new(vulkan_data, rows, cols)?;
+
+        if let Err(msg) = matrix.write(vulkan_data, data) {
+            matrix.clean(vulkan_data);
+            return Err(msg);
+        }
+
+        Ok(matrix)
+    }
+
+    // replaces the contents in place, work reading the matrix has to be complete
+    pub fn write(&self, vulkan_data: &VulkanData, data: &[T]) -> Result<(), String> {
+        if data.len() != self.rows * self.cols {
+            return Err(format!(
+                "{} elements do not fit a {} x {} matrix",
+                data.len(),
+                self.rows,
+                self.cols
+            ));
+        }
+
+        super::copy_data_to_buffer(vulkan_data, &self.mem_buffer, 0, data)
+    }
+
+    pub fn download(&self, vulkan_data: &VulkanData) -> Result<Vec<T>, String> {
+        super::read_data_from_buffer(vulkan_data, &self.mem_buffer, 0, self.rows * self.cols)
+    }
+
+    pub fn clean(self, vulkan_data: &VulkanData) {
+        // products of the matrix were cached with it bound
+        vulkan_data
+            .descriptor_allocator
+            .borrow_mut()
+            .forget_buffer(&vulkan_data.device, self.mem_buffer.buffer);
+
+        unsafe {
+            vulkan_data
+                .device
+                .destroy_buffer(self.mem_buffer.buffer, None);
+
+            vulkan_data
+                .device
+                .free_memory(self.mem_buffer.device_memory, None);
+        }
+    }
+}
+
+impl GpuMatrix<f32> {
+    // self * rhs with the kernel and configuration of vulkan_data, into a new matrix
+    pub fn multiply(
+        &self,
+        vulkan_data: &VulkanData,
+        rhs: &GpuMatrix<f32>,
+    ) -> Result<(GpuMatrix<f32>, DispatchReport), String> {
+        let _scope = vulkan_data.cpu_scope("gpu matrix multiply");
+
+        // the pipelines are built for N x N matrices
+        for matrix in [self, rhs] {
+            if matrix.rows != constants::N || matrix.cols != constants::N {
+                return Err(format!(
+                    "the kernels multiply {0} x {0} matrices, not {1} x {2}",
+                    constants::N,
+                    matrix.rows,
+                    matrix.cols
+                ));
+            }
+        }
+
+        let product = GpuMatrix::new(vulkan_data, self.rows, rhs.cols)?;
+
+        let report = super::dispatch(
+            vulkan_data,
+            vulkan_data.shader_variant,
+            vulkan_data.pipeline,
+            &vulkan_data.kernel_config,
+            MatrixBuffers {
+                a: &self.mem_buffer,
+                b: &rhs.mem_buffer,
+                c: &product.mem_buffer,
+            },
+        );
+
+        match report {
+            Ok(report) => Ok((product, report)),
+            Err(msg) => {
+                product.clean(vulkan_data);
+                Err(msg)
+            }
+        }
+    }
+}
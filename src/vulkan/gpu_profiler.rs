@@ -0,0 +1,221 @@
+use ash::vk;
+
+// pools are added as more scopes are recorded between two resolves, every scope takes a pair
+const QUERIES_PER_POOL: u32 = 64;
+
+// a scope read back from the GPU, in ns since an arbitrary GPU origin
+#[derive(Clone, Debug)]
+pub struct GpuSpan {
+    pub name: String,
+    pub start_ns: f64,
+    pub end_ns: f64,
+}
+
+impl GpuSpan {
+    pub fn duration_ms(&self) -> f32 {
+        ((self.end_ns - self.start_ns) / 1000000.0) as f32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GpuScopeId(usize);
+
+struct PendingScope {
+    name: String,
+    // index of the first of the scope's two queries over all pools, None without timestamps
+    first_query: Option<u32>,
+    ended: bool,
+}
+
+// named timestamp scopes around recorded commands
+pub struct GpuProfiler {
+    query_pools: Vec<vk::QueryPool>,
+    next_query: u32,
+    scopes: Vec<PendingScope>,
+    timestamp_period: f32,
+    // the bits of a timestamp that are meaningful, 0 when the queue cannot write timestamps
+    timestamp_mask: u64,
+}
+
+impl GpuProfiler {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceProperties,
+        queue_family: u32,
+    ) -> Self {
+        log::info!("creating gpu profiler");
+
+        let queue_family_properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        let timestamp_valid_bits = queue_family_properties
+            .get(queue_family as usize)
+            .map(|props| props.timestamp_valid_bits)
+            .unwrap_or(0);
+
+        log::info!("timestamp valid bits: {}", timestamp_valid_bits);
+
+        if timestamp_valid_bits == 0 {
+            log::warn!("the compute queue does not support timestamps, GPU scopes are not timed");
+        }
+
+        let timestamp_mask = match timestamp_valid_bits {
+            0 => 0,
+            64.. => u64::MAX,
+            bits => (1u64 << bits) - 1,
+        };
+
+        Self {
+            query_pools: Vec::new(),
+            next_query: 0,
+            scopes: Vec::new(),
+            timestamp_period: properties.limits.timestamp_period,
+            timestamp_mask,
+        }
+    }
+
+    pub fn timestamps_supported(&self) -> bool {
+        self.timestamp_mask != 0
+    }
+
+    fn query(&self, index: u32) -> (vk::QueryPool, u32) {
+        (
+            self.query_pools[(index / QUERIES_PER_POOL) as usize],
+            index % QUERIES_PER_POOL,
+        )
+    }
+
+    pub fn begin_scope(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+    ) -> Result<GpuScopeId, String> {
+        let first_query = match self.timestamps_supported() {
+            true => Some(self.next_query),
+            false => None,
+        };
+
+        if let Some(first_query) = first_query {
+            if first_query / QUERIES_PER_POOL == self.query_pools.len() as u32 {
+                self.query_pools
+                    .push(super::create_query_pool(device, QUERIES_PER_POOL)?);
+            }
+
+            self.next_query += 2;
+
+            let (query_pool, query) = self.query(first_query);
+
+            unsafe {
+                device.cmd_reset_query_pool(command_buffer, query_pool, query, 2);
+
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    query_pool,
+                    query,
+                );
+            }
+        }
+
+        self.scopes.push(PendingScope {
+            name: String::from(name),
+            first_query,
+            ended: false,
+        });
+
+        Ok(GpuScopeId(self.scopes.len() - 1))
+    }
+
+    pub fn end_scope(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        scope_id: GpuScopeId,
+    ) {
+        let scope = &mut self.scopes[scope_id.0];
+        scope.ended = true;
+
+        if let Some(first_query) = scope.first_query {
+            let (query_pool, query) = self.query(first_query + 1);
+
+            unsafe {
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    query,
+                );
+            }
+        }
+    }
+
+    // reads back every scope recorded since the last call, their command buffers must have
+    // completed; the queries are reused afterwards
+    pub fn resolve(&mut self, device: &ash::Device) -> Result<Vec<GpuSpan>, String> {
+        let scopes = std::mem::take(&mut self.scopes);
+        self.next_query = 0;
+
+        let mut spans = Vec::with_capacity(scopes.len());
+
+        for scope in scopes {
+            if !scope.ended {
+                return Err(format!("gpu scope {} was not ended", scope.name));
+            }
+
+            let first_query = match scope.first_query {
+                Some(first_query) => first_query,
+                None => continue,
+            };
+
+            let (query_pool, query) = self.query(first_query);
+
+            let mut query_data = [0u64; 2];
+
+            unsafe {
+                device
+                    .get_query_pool_results(
+                        query_pool,
+                        query,
+                        2,
+                        &mut query_data,
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                    .map_err(|_| String::from("failed to get query pool results"))?;
+            }
+
+            let start = query_data[0] & self.timestamp_mask;
+
+            // the counter may wrap around between the two timestamps
+            let ticks = query_data[1].wrapping_sub(query_data[0]) & self.timestamp_mask;
+
+            let start_ns = start as f64 * self.timestamp_period as f64;
+
+            spans.push(GpuSpan {
+                name: scope.name,
+                start_ns,
+                end_ns: start_ns + ticks as f64 * self.timestamp_period as f64,
+            });
+        }
+
+        Ok(spans)
+    }
+
+    // forgets the scopes recorded since the last resolve, for a command buffer that was not
+    // submitted; their queries are reused
+    pub fn discard_pending(&mut self) {
+        self.scopes.clear();
+        self.next_query = 0;
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning gpu profiler");
+
+        unsafe {
+            for query_pool in self.query_pools {
+                device.destroy_query_pool(query_pool, None);
+            }
+        }
+    }
+}
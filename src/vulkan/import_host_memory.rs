@@ -0,0 +1,227 @@
+use ash::prelude::VkResult;
+use ash::vk;
+
+use super::{MemBuffer, Pod, VulkanData};
+
+// VK_EXT_external_memory_host, the ash version used here has no loader for it; shaped like the
+// ash loaders so it can be replaced by one
+struct ExternalMemoryHost {
+    handle: vk::Device,
+    fp: vk::ExtExternalMemoryHostFn,
+}
+
+impl ExternalMemoryHost {
+    fn new(instance: &ash::Instance, device: &ash::Device) -> Self {
+        let handle = device.handle();
+
+        let fp = vk::ExtExternalMemoryHostFn::load(|name| unsafe {
+            instance
+                .get_device_proc_addr(handle, name.as_ptr())
+                .map_or(std::ptr::null(), |f| f as *const std::ffi::c_void)
+        });
+
+        Self { handle, fp }
+    }
+
+    unsafe fn get_memory_host_pointer_properties(
+        &self,
+        handle_type: vk::ExternalMemoryHandleTypeFlags,
+        host_pointer: *const std::ffi::c_void,
+    ) -> VkResult<vk::MemoryHostPointerPropertiesEXT> {
+        let mut properties = vk::MemoryHostPointerPropertiesEXT::default();
+
+        (self.fp.get_memory_host_pointer_properties_ext)(
+            self.handle,
+            handle_type,
+            host_pointer,
+            &mut properties,
+        )
+        .result_with_success(properties)
+    }
+}
+
+// host memory that can be imported: its start is aligned to minImportedHostPointerAlignment and
+// the allocation is padded to a multiple of it. slices of a whole number of alignment units
+// from its start are imported, e.g. all of it when its size is such a multiple; the memory is
+// zeroed, which is a valid value of every Pod type
+pub struct AlignedHostMemory<T: Pod> {
+    ptr: std::ptr::NonNull<T>,
+    len: usize,
+    layout: std::alloc::Layout,
+}
+
+impl<T: Pod> AlignedHostMemory<T> {
+    // aligned for T only when the device cannot import host memory
+    pub fn new(vulkan_data: &VulkanData, len: usize) -> Self {
+        let alignment = vulkan_data
+            .device_capabilities
+            .imported_host_pointer_alignment
+            .map_or(1, |alignment| alignment as usize)
+            .max(std::mem::align_of::<T>());
+
+        let size = (len * std::mem::size_of::<T>()).div_ceil(alignment).max(1) * alignment;
+
+        let layout = std::alloc::Layout::from_size_align(size, alignment)
+            .expect("invalid aligned host memory layout");
+
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) }.cast::<T>();
+
+        let ptr = match std::ptr::NonNull::new(ptr) {
+            Some(ptr) => ptr,
+            None => std::alloc::handle_alloc_error(layout),
+        };
+
+        Self { ptr, len, layout }
+    }
+}
+
+impl<T: Pod> std::ops::Deref for AlignedHostMemory<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Pod> std::ops::DerefMut for AlignedHostMemory<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Pod> Drop for AlignedHostMemory<T> {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr().cast::<u8>(), self.layout) };
+    }
+}
+
+// wraps the caller's memory in a buffer without copying it; the data has to outlive every
+// submission using the buffer and stay unchanged while the device reads it. both its start and
+// its size have to be aligned to minImportedHostPointerAlignment, which a Vec almost never is;
+// allocate it with `AlignedHostMemory`
+pub fn import_host_memory<T: Pod>(
+    vulkan_data: &VulkanData,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+) -> Result<MemBuffer, String> {
+    log::info!("importing host memory");
+
+    let alignment = vulkan_data
+        .device_capabilities
+        .imported_host_pointer_alignment
+        .ok_or_else(|| String::from("host pointers cannot be imported"))?;
+
+    let size = std::mem::size_of_val(data) as vk::DeviceSize;
+    let host_pointer = data.as_ptr().cast::<std::ffi::c_void>();
+
+    // the whole allocation has to be aligned, not only its start
+    if !(host_pointer as vk::DeviceSize).is_multiple_of(alignment)
+        || !size.is_multiple_of(alignment)
+    {
+        return Err(format!(
+            "host memory at {:?} of {} bytes is not aligned to {} bytes",
+            host_pointer, size, alignment
+        ));
+    }
+
+    let external_memory_host = ExternalMemoryHost::new(&vulkan_data.instance, &vulkan_data.device);
+
+    let host_pointer_properties = unsafe {
+        external_memory_host
+            .get_memory_host_pointer_properties(
+                vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT,
+                host_pointer,
+            )
+            .map_err(|_| String::from("failed to get memory host pointer properties"))?
+    };
+
+    // buffer
+    let mut external_memory_buffer_info = vk::ExternalMemoryBufferCreateInfo::builder()
+        .handle_types(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT)
+        .build();
+
+    let buffer_create_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .push_next(&mut external_memory_buffer_info)
+        .build();
+
+    let buffer = unsafe {
+        vulkan_data
+            .device
+            .create_buffer(&buffer_create_info, None)
+            .map_err(|_| String::from("failed to create buffer"))?
+    };
+
+    let memory_requirements = unsafe { vulkan_data.device.get_buffer_memory_requirements(buffer) };
+
+    let memory_type_bits =
+        memory_requirements.memory_type_bits & host_pointer_properties.memory_type_bits;
+
+    if memory_type_bits == 0 || memory_requirements.size > size {
+        unsafe {
+            vulkan_data.device.destroy_buffer(buffer, None);
+        }
+
+        return Err(String::from(
+            "no memory type can back the buffer with the imported host memory",
+        ));
+    }
+
+    let memory_type = memory_type_bits.trailing_zeros();
+
+    // memory
+    let mut import_info = vk::ImportMemoryHostPointerInfoEXT::builder()
+        .handle_type(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT)
+        .host_pointer(host_pointer as *mut std::ffi::c_void)
+        .build();
+
+    let allocate_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(size)
+        .memory_type_index(memory_type)
+        .push_next(&mut import_info)
+        .build();
+
+    let device_memory = unsafe { vulkan_data.device.allocate_memory(&allocate_info, None) };
+
+    let device_memory = match device_memory {
+        Ok(device_memory) => device_memory,
+        Err(_) => {
+            unsafe {
+                vulkan_data.device.destroy_buffer(buffer, None);
+            }
+
+            return Err(String::from("failed to import host memory"));
+        }
+    };
+
+    let bind_result = unsafe {
+        vulkan_data
+            .device
+            .bind_buffer_memory(buffer, device_memory, 0)
+    };
+
+    if bind_result.is_err() {
+        unsafe {
+            vulkan_data.device.destroy_buffer(buffer, None);
+            vulkan_data.device.free_memory(device_memory, None);
+        }
+
+        return Err(String::from("failed to bind buffer and memory"));
+    }
+
+    let memory_properties = unsafe {
+        vulkan_data
+            .instance
+            .get_physical_device_memory_properties(vulkan_data.physical_device)
+    };
+
+    Ok(MemBuffer {
+        buffer,
+        device_memory,
+        size,
+        allocation_size: size,
+        memory_flags: memory_properties.memory_types[memory_type as usize].property_flags,
+    })
+}
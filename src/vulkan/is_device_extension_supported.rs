@@ -0,0 +1,18 @@
+use ash::vk;
+
+pub fn is_device_extension_supported(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    name: &std::ffi::CStr,
+) -> bool {
+    let extensions =
+        match unsafe { instance.enumerate_device_extension_properties(physical_device) } {
+            Ok(extensions) => extensions,
+            Err(_) => return false,
+        };
+
+    extensions.iter().any(|extension| {
+        let extension_name = unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) };
+        extension_name == name
+    })
+}
@@ -0,0 +1,483 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+use crate::shader_variants::ShaderVariant;
+
+use super::{CooperativeMatrixShape, DeviceCapabilities};
+
+// specialization constants of the matrix multiplication kernels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelConfig {
+    // edge of the square output tile computed by one workgroup
+    pub tile_size: u32,
+    // depth of the tiles of A and B staged in shared memory per pass
+    pub tile_k: u32,
+    // edge of the square output block computed by one invocation, rows of a column for the
+    // subgroup kernels
+    pub thread_tile: u32,
+    // number of shared memory buffers, 2 overlaps loading with computing
+    pub stages: u32,
+    // shape of the cooperative matrix multiply, only used by the cooperative matrix kernel
+    #[serde(default)]
+    pub matrix_shape: CooperativeMatrixShape,
+    // subgroup size required from the pipeline, 0 leaves it to the driver
+    #[serde(default)]
+    pub subgroup_size: u32,
+}
+
+impl KernelConfig {
+    pub fn default_for(shader_variant: ShaderVariant, capabilities: &DeviceCapabilities) -> Self {
+        match shader_variant.kernel() {
+            ShaderVariant::Blocked => Self {
+                tile_size: 64,
+                tile_k: 16,
+                thread_tile: 4,
+                stages: 2,
+                ..Self::one_per_invocation(64)
+            },
+            ShaderVariant::Cooperative => Self::cooperative_kernel(capabilities),
+            ShaderVariant::SubgroupReduce => {
+                Self::subgroup_kernel(16, 1, preferred_subgroup_size(capabilities))
+            }
+            ShaderVariant::SubgroupShuffle => {
+                Self::subgroup_kernel(32, 4, preferred_subgroup_size(capabilities))
+            }
+            _ => Self::one_per_invocation(constants::WORKGROUP_SIZE),
+        }
+    }
+
+    // the shape used by kernels computing one element of C per invocation from square tiles
+    fn one_per_invocation(tile_size: u32) -> Self {
+        Self {
+            tile_size,
+            tile_k: tile_size,
+            thread_tile: 1,
+            stages: 1,
+            matrix_shape: CooperativeMatrixShape::default(),
+            subgroup_size: 0,
+        }
+    }
+
+    // the first shape the device reports that passes the checks at the default tile sizes; the
+    // first one reported when none does, so checking the configuration tells why
+    fn cooperative_kernel(capabilities: &DeviceCapabilities) -> Self {
+        let with_shape = |matrix_shape| Self {
+            tile_size: 64,
+            tile_k: 32,
+            thread_tile: 4,
+            matrix_shape,
+            ..Self::one_per_invocation(64)
+        };
+
+        capabilities
+            .cooperative_matrix_shapes
+            .iter()
+            .map(|&matrix_shape| with_shape(matrix_shape))
+            .find(|config| {
+                config
+                    .check_limits(ShaderVariant::Cooperative, capabilities, constants::N)
+                    .is_ok()
+            })
+            .unwrap_or_else(|| {
+                with_shape(
+                    capabilities
+                        .cooperative_matrix_shapes
+                        .first()
+                        .copied()
+                        .unwrap_or_default(),
+                )
+            })
+    }
+
+    // the subgroup kernels don't stage tiles in shared memory
+    fn subgroup_kernel(tile_size: u32, thread_tile: u32, subgroup_size: u32) -> Self {
+        Self {
+            thread_tile,
+            subgroup_size,
+            ..Self::one_per_invocation(tile_size)
+        }
+    }
+
+    // configurations worth timing when autotuning the given kernel
+    pub fn candidates(
+        shader_variant: ShaderVariant,
+        capabilities: &DeviceCapabilities,
+    ) -> Vec<Self> {
+        match shader_variant.kernel() {
+            ShaderVariant::Blocked => {
+                let mut candidates = Vec::new();
+
+                for tile_size in [32, 64, 128] {
+                    for tile_k in [8, 16, 32] {
+                        for thread_tile in [4, 8] {
+                            for stages in [1, 2] {
+                                candidates.push(Self {
+                                    tile_k,
+                                    thread_tile,
+                                    stages,
+                                    ..Self::one_per_invocation(tile_size)
+                                });
+                            }
+                        }
+                    }
+                }
+
+                candidates
+            }
+            ShaderVariant::Cooperative => {
+                let mut candidates = Vec::new();
+
+                for &matrix_shape in &capabilities.cooperative_matrix_shapes {
+                    for tile_size in [32, 64, 128] {
+                        for tile_k in [16, 32, 64] {
+                            for thread_tile in [2, 4, 8] {
+                                candidates.push(Self {
+                                    tile_k,
+                                    thread_tile,
+                                    matrix_shape,
+                                    ..Self::one_per_invocation(tile_size)
+                                });
+                            }
+                        }
+                    }
+                }
+
+                candidates
+            }
+            ShaderVariant::SubgroupReduce | ShaderVariant::SubgroupShuffle => {
+                let mut candidates = Vec::new();
+
+                for tile_size in [16, 32, 64, 128] {
+                    for thread_tile in [1, 2, 4, 8] {
+                        for subgroup_size in candidate_subgroup_sizes(capabilities) {
+                            candidates.push(Self::subgroup_kernel(
+                                tile_size,
+                                thread_tile,
+                                subgroup_size,
+                            ));
+                        }
+                    }
+                }
+
+                candidates
+            }
+            _ => [4, 8, 16, 32, 64, 128]
+                .into_iter()
+                .map(Self::one_per_invocation)
+                .collect(),
+        }
+    }
+
+    pub fn workgroup_size(&self, shader_variant: ShaderVariant) -> [u32; 3] {
+        match shader_variant.kernel() {
+            // one dimensional so full subgroups can be required
+            ShaderVariant::SubgroupReduce | ShaderVariant::SubgroupShuffle => {
+                [self.tile_size * self.tile_size / self.thread_tile, 1, 1]
+            }
+            _ => {
+                let size = self.tile_size / self.thread_tile;
+
+                [size, size, 1]
+            }
+        }
+    }
+
+    pub fn group_count(&self, n: usize) -> [u32; 3] {
+        let groups = (n as u32) / self.tile_size;
+
+        [groups, groups, 1]
+    }
+
+    // values for constant ids 0..=9: local_size_x_id, local_size_y_id, local_size_z_id, BLOCK_SIZE,
+    // TILE_K, THREAD_TILE, STAGES, MATRIX_M, MATRIX_N, MATRIX_K; kernels ignore the ids they don't
+    // declare
+    pub fn specialization_data(&self, shader_variant: ShaderVariant) -> Vec<u32> {
+        let [x, y, z] = self.workgroup_size(shader_variant);
+
+        vec![
+            x,
+            y,
+            z,
+            self.tile_size,
+            self.tile_k,
+            self.thread_tile,
+            self.stages,
+            self.matrix_shape.m,
+            self.matrix_shape.n,
+            self.matrix_shape.k,
+        ]
+    }
+
+    pub fn shared_memory_size(&self, shader_variant: ShaderVariant) -> u32 {
+        // the cooperative matrix kernel stages its tiles as f16
+        let element_size = match shader_variant.kernel() {
+            ShaderVariant::Cooperative => 2,
+            ShaderVariant::SubgroupReduce | ShaderVariant::SubgroupShuffle => 0,
+            _ => std::mem::size_of::<f32>() as u32,
+        };
+
+        // a tile of A and a tile of B per stage
+        self.stages * 2 * self.tile_size * self.tile_k * element_size
+    }
+
+    // checks the shape against what the kernel supports
+    fn check_shape(
+        &self,
+        shader_variant: ShaderVariant,
+        capabilities: &DeviceCapabilities,
+        n: usize,
+    ) -> Result<(), String> {
+        if self.tile_size == 0 || !n.is_multiple_of(self.tile_size as usize) {
+            return Err(format!(
+                "tile size {} does not divide the matrix size {}",
+                self.tile_size, n
+            ));
+        }
+
+        match shader_variant.kernel() {
+            ShaderVariant::Blocked => {
+                if self.thread_tile == 0
+                    || !self.thread_tile.is_multiple_of(4)
+                    || self.thread_tile > constants::MAX_THREAD_TILE
+                    || !self.tile_size.is_multiple_of(self.thread_tile)
+                {
+                    return Err(format!(
+                        "thread tile {} must be a multiple of 4 up to {} dividing the tile size {}",
+                        self.thread_tile,
+                        constants::MAX_THREAD_TILE,
+                        self.tile_size
+                    ));
+                }
+
+                if self.tile_k == 0
+                    || !self.tile_k.is_multiple_of(4)
+                    || !n.is_multiple_of(self.tile_k as usize)
+                {
+                    return Err(format!(
+                        "tile depth {} must be a multiple of 4 dividing the matrix size {}",
+                        self.tile_k, n
+                    ));
+                }
+
+                if self.stages != 1 && self.stages != 2 {
+                    return Err(format!("{} stages are not supported", self.stages));
+                }
+            }
+            ShaderVariant::Cooperative => {
+                let shape = self.matrix_shape;
+
+                if !capabilities.cooperative_matrix_shapes.contains(&shape) {
+                    return Err(format!(
+                        "cooperative matrix shape {:?} is not supported by the device",
+                        shape
+                    ));
+                }
+
+                if self.thread_tile == 0 || !self.tile_size.is_multiple_of(self.thread_tile) {
+                    return Err(format!(
+                        "thread tile {} does not divide the tile size {}",
+                        self.thread_tile, self.tile_size
+                    ));
+                }
+
+                if !self.tile_size.is_multiple_of(shape.m)
+                    || !self.tile_size.is_multiple_of(shape.n)
+                {
+                    return Err(format!(
+                        "tile size {} is not a multiple of the matrix shape {:?}",
+                        self.tile_size, shape
+                    ));
+                }
+
+                // loads and stores of cooperative matrices need 16 byte aligned rows
+                if !self.tile_k.is_multiple_of(shape.k.max(8))
+                    || !n.is_multiple_of(self.tile_k as usize)
+                {
+                    return Err(format!(
+                        "tile depth {} must be a multiple of {} and 8 dividing the matrix size {}",
+                        self.tile_k, shape.k, n
+                    ));
+                }
+
+                if self.stages != 1 {
+                    return Err(format!("{} stages are not supported", self.stages));
+                }
+
+                // every subgroup accumulates at most MAX_COOPERATIVE_TILES matrices
+                let invocations = self.workgroup_size(shader_variant).iter().product::<u32>();
+                let subgroups = invocations / capabilities.subgroup_size.max(1);
+                let tiles = (self.tile_size / shape.m) * (self.tile_size / shape.n);
+
+                if tiles > subgroups * constants::MAX_COOPERATIVE_TILES {
+                    return Err(format!(
+                        "{} subgroups cannot hold the {} cooperative matrices of a tile",
+                        subgroups, tiles
+                    ));
+                }
+            }
+            ShaderVariant::SubgroupReduce | ShaderVariant::SubgroupShuffle => {
+                if *self
+                    != Self::subgroup_kernel(self.tile_size, self.thread_tile, self.subgroup_size)
+                {
+                    return Err(format!(
+                        "shader variant {} only uses the tile size, thread tile and subgroup size",
+                        shader_variant.name()
+                    ));
+                }
+
+                if self.thread_tile == 0
+                    || self.thread_tile > constants::MAX_THREAD_TILE
+                    || !self.tile_size.is_multiple_of(self.thread_tile)
+                {
+                    return Err(format!(
+                        "thread tile {} must be at most {} and divide the tile size {}",
+                        self.thread_tile,
+                        constants::MAX_THREAD_TILE,
+                        self.tile_size
+                    ));
+                }
+
+                let subgroup_size = match self.subgroup_size {
+                    0 => capabilities
+                        .subgroup_size_control
+                        .as_ref()
+                        .map_or(capabilities.subgroup_size, |control| {
+                            control.max_subgroup_size
+                        }),
+                    subgroup_size => subgroup_size,
+                };
+
+                // a subgroup covers consecutive columns of the tile and the whole rows of A
+                let invocations = self.workgroup_size(shader_variant)[0];
+
+                if !self.tile_size.is_multiple_of(subgroup_size)
+                    || !invocations.is_multiple_of(subgroup_size)
+                    || !n.is_multiple_of(subgroup_size as usize)
+                {
+                    return Err(format!(
+                        "subgroup size {} does not divide the tile size {} and {} invocations",
+                        subgroup_size, self.tile_size, invocations
+                    ));
+                }
+
+                self.check_subgroup_size(capabilities, invocations)?;
+            }
+            _ => {
+                if *self != Self::one_per_invocation(self.tile_size) {
+                    return Err(format!(
+                        "shader variant {} computes one element per invocation from square tiles",
+                        shader_variant.name()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_subgroup_size(
+        &self,
+        capabilities: &DeviceCapabilities,
+        invocations: u32,
+    ) -> Result<(), String> {
+        if self.subgroup_size == 0 {
+            return Ok(());
+        }
+
+        let control = capabilities
+            .subgroup_size_control
+            .as_ref()
+            .ok_or(String::from("subgroup size control is not supported"))?;
+
+        if !self.subgroup_size.is_power_of_two()
+            || self.subgroup_size < control.min_subgroup_size
+            || self.subgroup_size > control.max_subgroup_size
+        {
+            return Err(format!(
+                "subgroup size {} is not a power of two in {}..={}",
+                self.subgroup_size, control.min_subgroup_size, control.max_subgroup_size
+            ));
+        }
+
+        // pipelines requiring a subgroup size also require full subgroups
+        if !control.compute_full_subgroups {
+            return Err(String::from("full compute subgroups are not supported"));
+        }
+
+        if invocations > self.subgroup_size * control.max_compute_workgroup_subgroups {
+            return Err(format!(
+                "{} invocations exceed maxComputeWorkgroupSubgroups {}",
+                invocations, control.max_compute_workgroup_subgroups
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_limits(
+        &self,
+        shader_variant: ShaderVariant,
+        capabilities: &DeviceCapabilities,
+        n: usize,
+    ) -> Result<(), String> {
+        self.check_shape(shader_variant, capabilities, n)?;
+
+        let limits = &capabilities.limits;
+
+        let workgroup_size = self.workgroup_size(shader_variant);
+        let group_count = self.group_count(n);
+        for i in 0..3 {
+            if workgroup_size[i] > limits.max_compute_work_group_size[i] {
+                return Err(format!(
+                    "workgroup size {:?} exceeds maxComputeWorkGroupSize {:?}",
+                    workgroup_size, limits.max_compute_work_group_size
+                ));
+            }
+
+            if group_count[i] > limits.max_compute_work_group_count[i] {
+                return Err(format!(
+                    "group count {:?} exceeds maxComputeWorkGroupCount {:?}",
+                    group_count, limits.max_compute_work_group_count
+                ));
+            }
+        }
+
+        if workgroup_size.iter().product::<u32>() > limits.max_compute_work_group_invocations {
+            return Err(format!(
+                "workgroup size {:?} exceeds maxComputeWorkGroupInvocations {}",
+                workgroup_size, limits.max_compute_work_group_invocations
+            ));
+        }
+
+        let shared_memory_size = self.shared_memory_size(shader_variant);
+
+        if shared_memory_size > limits.max_compute_shared_memory_size {
+            return Err(format!(
+                "shared memory size {} exceeds maxComputeSharedMemorySize {}",
+                shared_memory_size, limits.max_compute_shared_memory_size
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// a fixed subgroup size keeps gl_SubgroupSize from varying between dispatches
+fn preferred_subgroup_size(capabilities: &DeviceCapabilities) -> u32 {
+    match &capabilities.subgroup_size_control {
+        Some(control) if control.compute_full_subgroups => capabilities
+            .subgroup_size
+            .clamp(control.min_subgroup_size, control.max_subgroup_size),
+        _ => 0,
+    }
+}
+
+fn candidate_subgroup_sizes(capabilities: &DeviceCapabilities) -> Vec<u32> {
+    match &capabilities.subgroup_size_control {
+        Some(control) if control.compute_full_subgroups => (0..32)
+            .map(|shift| 1 << shift)
+            .filter(|size| (control.min_subgroup_size..=control.max_subgroup_size).contains(size))
+            .collect(),
+        _ => vec![0],
+    }
+}
@@ -0,0 +1,81 @@
+use ash::vk;
+
+use super::{MemBuffer, VulkanData};
+
+// the whole buffer rounded up to nonCoherentAtomSize, or to the end of the memory
+fn mapped_range(vulkan_data: &VulkanData, mem_buffer: &MemBuffer) -> vk::MappedMemoryRange {
+    let atom_size = vulkan_data
+        .device_capabilities
+        .limits
+        .non_coherent_atom_size
+        .max(1);
+
+    let size = mem_buffer
+        .size
+        .div_ceil(atom_size)
+        .saturating_mul(atom_size)
+        .min(mem_buffer.allocation_size);
+
+    vk::MappedMemoryRange::builder()
+        .memory(mem_buffer.device_memory)
+        .offset(0)
+        .size(size)
+        .build()
+}
+
+// maps the buffer and makes device writes to non-coherent memory visible to the host
+pub fn map_mem_buffer(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+) -> Result<*mut std::ffi::c_void, String> {
+    let range = mapped_range(vulkan_data, mem_buffer);
+
+    let mapped_data_ptr = unsafe {
+        vulkan_data
+            .device
+            .map_memory(
+                mem_buffer.device_memory,
+                0,
+                range.size,
+                vk::MemoryMapFlags::empty(),
+            )
+            .map_err(|_| String::from("failed to map buffer memory"))?
+    };
+
+    invalidate_mem_buffer(vulkan_data, mem_buffer)?;
+
+    Ok(mapped_data_ptr)
+}
+
+// makes host writes to non-coherent memory available to the device, before the submission
+// reading them
+pub fn flush_mem_buffer(vulkan_data: &VulkanData, mem_buffer: &MemBuffer) -> Result<(), String> {
+    if mem_buffer.host_coherent() {
+        return Ok(());
+    }
+
+    unsafe {
+        vulkan_data
+            .device
+            .flush_mapped_memory_ranges(&[mapped_range(vulkan_data, mem_buffer)])
+            .map_err(|_| String::from("failed to flush mapped memory"))
+    }
+}
+
+// makes device writes to non-coherent memory visible to the host, after the submission
+// writing them completed
+pub fn invalidate_mem_buffer(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+) -> Result<(), String> {
+    if mem_buffer.host_coherent() {
+        return Ok(());
+    }
+
+    unsafe {
+        vulkan_data
+            .device
+            .invalidate_mapped_memory_ranges(&[mapped_range(vulkan_data, mem_buffer)])
+            .map_err(|_| String::from("failed to invalidate mapped memory"))
+    }
+}
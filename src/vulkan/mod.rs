@@ -1,5 +1,5 @@
 mod allocate_command_buffer;
-mod allocate_descriptor_set;
+mod autotune;
 mod begin_command_buffer;
 mod check_instance_version;
 mod check_required_instance_extensions;
@@ -8,25 +8,49 @@ mod create_command_pool;
 mod create_descriptor_pool;
 mod create_descriptor_set_layout;
 mod create_entry;
+mod create_host_mem_buffer;
 mod create_instance;
 mod create_logical_device;
+mod create_matrix_mem_buffer;
 mod create_mem_buffer;
 mod create_pipeline;
 mod create_pipeline_layout;
+mod create_pipeline_statistics_query_pool;
 mod create_query_pool;
 mod create_shader_module;
+mod debug_messenger;
 mod debug_utils;
+mod descriptor_allocator;
+mod device_report;
+mod dispatch;
+mod get_cooperative_matrix_shapes;
+mod get_device_capabilities;
+mod get_memory_budget;
 mod get_physical_device;
 mod get_physical_device_properties;
 mod get_queue;
 mod get_queue_family;
+mod gpu_matrix;
+mod gpu_profiler;
+mod import_host_memory;
+mod is_device_extension_supported;
+mod kernel_config;
+mod map_mem_buffer;
+mod multiply_out_of_core;
+mod performance_counters;
+mod pod;
+mod profiler;
 mod read_data_from_buffer;
+mod recorded_sequence;
+mod roofline;
 mod submit;
-mod update_descriptor_set;
+mod task_graph;
+mod timestamp_calibrator;
 mod vulkan_data;
+mod workgroup_clocks;
 
 use allocate_command_buffer::*;
-use allocate_descriptor_set::*;
+use autotune::*;
 use begin_command_buffer::*;
 use check_instance_version::*;
 use check_required_instance_extensions::*;
@@ -35,19 +59,43 @@ use create_command_pool::*;
 use create_descriptor_pool::*;
 use create_descriptor_set_layout::*;
 use create_entry::*;
+use create_host_mem_buffer::*;
 use create_instance::*;
 use create_logical_device::*;
+use create_matrix_mem_buffer::*;
 use create_mem_buffer::*;
 use create_pipeline::*;
 use create_pipeline_layout::*;
+use create_pipeline_statistics_query_pool::*;
 use create_query_pool::*;
 use create_shader_module::*;
+pub use debug_messenger::*;
 use debug_utils::*;
+pub use descriptor_allocator::*;
+pub use device_report::*;
+pub use dispatch::*;
+pub use get_cooperative_matrix_shapes::*;
+pub use get_device_capabilities::*;
+pub use get_memory_budget::*;
 use get_physical_device::*;
 use get_physical_device_properties::*;
 use get_queue::*;
 use get_queue_family::*;
+pub use gpu_matrix::*;
+pub use gpu_profiler::*;
+pub use import_host_memory::*;
+use is_device_extension_supported::*;
+pub use kernel_config::*;
+use map_mem_buffer::*;
+pub use multiply_out_of_core::*;
+pub use performance_counters::*;
+pub use pod::*;
+pub use profiler::*;
 use read_data_from_buffer::*;
-use submit::*;
-use update_descriptor_set::*;
+pub use recorded_sequence::*;
+pub use roofline::*;
+pub use submit::*;
+pub use task_graph::*;
+pub use timestamp_calibrator::*;
 pub use vulkan_data::*;
+pub use workgroup_clocks::*;
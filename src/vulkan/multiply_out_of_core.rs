@@ -0,0 +1,694 @@
+use ash::vk;
+
+use crate::constants;
+use crate::shader_interface::PanelPushConst;
+use crate::shader_variants::ShaderVariant;
+
+use super::{HostAccess, KernelConfig, MemBuffer, MemoryBudget, VulkanData};
+
+const FLOAT_SIZE: vk::DeviceSize = std::mem::size_of::<f32>() as vk::DeviceSize;
+
+// device buffers of one step: a panel of A, a panel of B and the tile of C they accumulate into
+fn device_size(tile_size: u32) -> vk::DeviceSize {
+    3 * (tile_size as vk::DeviceSize).pow(2) * FLOAT_SIZE
+}
+
+// the largest tile whose buffers fit twice in the device local budget, so other work keeps some
+// room; a multiple of the workgroup size within the dispatch and buffer limits
+pub fn out_of_core_tile_size(
+    memory_budget: &MemoryBudget,
+    limits: &vk::PhysicalDeviceLimits,
+    n: usize,
+) -> Option<u32> {
+    let padded_n = (n as u32).div_ceil(constants::WORKGROUP_SIZE) * constants::WORKGROUP_SIZE;
+
+    let mut tile_size = padded_n;
+
+    while tile_size >= constants::WORKGROUP_SIZE {
+        let fits_budget = memory_budget.concurrent_jobs(device_size(tile_size)) >= 2;
+        let fits_limits = tile_size / constants::WORKGROUP_SIZE
+            <= limits.max_compute_work_group_count[0].min(limits.max_compute_work_group_count[1])
+            && (tile_size as vk::DeviceSize).pow(2) * FLOAT_SIZE
+                <= limits.max_storage_buffer_range as vk::DeviceSize;
+
+        if fits_budget && fits_limits {
+            return Some(tile_size);
+        }
+
+        // halving keeps the tile a multiple of the workgroup size
+        tile_size = ((tile_size / 2) / constants::WORKGROUP_SIZE) * constants::WORKGROUP_SIZE;
+    }
+
+    None
+}
+
+#[derive(Default)]
+struct PanelResources {
+    panel_a: MemBuffer,
+    panel_b: MemBuffer,
+    tile_c: MemBuffer,
+    // both panels, A first
+    upload: MemBuffer,
+    readback: MemBuffer,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl PanelResources {
+    fn new(vulkan_data: &VulkanData, tile_size: u32) -> Result<Self, String> {
+        log::info!("creating out-of-core resources for tile size {}", tile_size);
+
+        let mut resources = Self::default();
+
+        // a refused allocation is expected when the budget is tight, whatever was created before
+        // it is destroyed; the handles not created yet are null
+        if let Err(msg) = resources.create(vulkan_data, tile_size) {
+            resources.clean(&vulkan_data.device);
+            return Err(msg);
+        }
+
+        Ok(resources)
+    }
+
+    fn create(&mut self, vulkan_data: &VulkanData, tile_size: u32) -> Result<(), String> {
+        let device = &vulkan_data.device;
+        let tile_bytes = (tile_size as vk::DeviceSize).pow(2) * FLOAT_SIZE;
+
+        let create_buffer = |size, usage, memory_flags| {
+            super::create_mem_buffer(
+                &vulkan_data.instance,
+                vulkan_data.physical_device,
+                device,
+                size,
+                usage,
+                memory_flags,
+                vulkan_data.device_capabilities.memory_budget,
+            )
+        };
+
+        self.panel_a = create_buffer(
+            tile_bytes,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.panel_b = create_buffer(
+            tile_bytes,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.tile_c = create_buffer(
+            tile_bytes,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.upload = super::create_host_mem_buffer(
+            vulkan_data,
+            2 * tile_bytes,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            HostAccess::Upload,
+        )?;
+        self.readback = super::create_host_mem_buffer(
+            vulkan_data,
+            tile_bytes,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            HostAccess::Readback,
+        )?;
+
+        let shader_variant = ShaderVariant::GemmPanel;
+        let shader_bindings = shader_variant.bindings();
+
+        self.descriptor_set_layout = super::create_descriptor_set_layout(device, shader_bindings)?;
+
+        self.pipeline_layout = super::create_pipeline_layout(
+            device,
+            self.descriptor_set_layout,
+            shader_variant.push_constant_size(),
+        )?;
+
+        let shader_module = super::create_shader_module(device, shader_variant.spv_path())?;
+
+        let pipeline = super::create_pipeline(
+            device,
+            shader_module,
+            self.pipeline_layout,
+            shader_variant,
+            &KernelConfig::default_for(shader_variant, &vulkan_data.device_capabilities),
+        );
+
+        unsafe {
+            device.destroy_shader_module(shader_module, None);
+        }
+
+        self.pipeline = pipeline?;
+
+        self.descriptor_pool = super::create_descriptor_pool(
+            device,
+            shader_bindings,
+            1,
+            vk::DescriptorPoolCreateFlags::empty(),
+        )?;
+
+        let layouts = [self.descriptor_set_layout];
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&layouts)
+            .build();
+
+        self.descriptor_set = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .map_err(|_| String::from("failed to allocate descriptor set"))?[0]
+        };
+
+        let infos = [&self.panel_a, &self.panel_b, &self.tile_c].map(|mem_buffer| {
+            vk::DescriptorBufferInfo::builder()
+                .buffer(mem_buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()
+        });
+
+        let write_descriptor_set = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&infos)
+            .build();
+
+        unsafe {
+            device.update_descriptor_sets(&[write_descriptor_set], &[]);
+        }
+
+        vulkan_data
+            .debug_utils
+            .set_name(self.pipeline, "out-of-core pipeline");
+        vulkan_data
+            .debug_utils
+            .set_name(self.tile_c.buffer, "out-of-core C tile buffer");
+
+        Ok(())
+    }
+
+    fn clean(self, device: &ash::Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+
+            for mem_buffer in [
+                self.panel_a,
+                self.panel_b,
+                self.tile_c,
+                self.upload,
+                self.readback,
+            ] {
+                device.destroy_buffer(mem_buffer.buffer, None);
+                device.free_memory(mem_buffer.device_memory, None);
+            }
+        }
+    }
+}
+
+// copies the block of an n x n row major matrix starting at (row, col) into a square tile,
+// zeros where the block reaches past the matrix
+fn pack_block(
+    matrix: &[f32],
+    n: usize,
+    row: usize,
+    col: usize,
+    tile: &mut [f32],
+    tile_size: usize,
+) {
+    let cols = n.saturating_sub(col).min(tile_size);
+
+    for (r, tile_row) in tile.chunks_exact_mut(tile_size).enumerate() {
+        tile_row.fill(0.0);
+
+        if row + r < n {
+            let start = (row + r) * n + col;
+            tile_row[..cols].copy_from_slice(&matrix[start..start + cols]);
+        }
+    }
+}
+
+fn unpack_block(
+    tile: &[f32],
+    tile_size: usize,
+    matrix: &mut [f32],
+    n: usize,
+    row: usize,
+    col: usize,
+) {
+    let cols = n.saturating_sub(col).min(tile_size);
+
+    for (r, tile_row) in tile.chunks_exact(tile_size).enumerate() {
+        if row + r < n {
+            let start = (row + r) * n + col;
+            matrix[start..start + cols].copy_from_slice(&tile_row[..cols]);
+        }
+    }
+}
+
+// records and runs one step: uploads the staged panels, clears C on the first step, accumulates
+// and reads C back on the last one
+fn run_step(
+    vulkan_data: &VulkanData,
+    resources: &PanelResources,
+    tile_size: u32,
+    first: bool,
+    last: bool,
+) -> Result<(), String> {
+    let device = &vulkan_data.device;
+    let tile_bytes = (tile_size as vk::DeviceSize).pow(2) * FLOAT_SIZE;
+
+    let command_buffer = super::allocate_command_buffer(vulkan_data)?;
+
+    super::begin_command_buffer(vulkan_data, command_buffer)?;
+
+    let scope_id = vulkan_data.profiler.borrow_mut().begin_gpu_scope(
+        device,
+        command_buffer,
+        "out-of-core step",
+    )?;
+
+    let push_const = PanelPushConst {
+        tileSize: tile_size,
+        panelDepth: tile_size,
+    };
+
+    let group_count = tile_size / constants::WORKGROUP_SIZE;
+
+    let ended = unsafe {
+        device.cmd_copy_buffer(
+            command_buffer,
+            resources.upload.buffer,
+            resources.panel_a.buffer,
+            &[vk::BufferCopy::builder().size(tile_bytes).build()],
+        );
+
+        device.cmd_copy_buffer(
+            command_buffer,
+            resources.upload.buffer,
+            resources.panel_b.buffer,
+            &[vk::BufferCopy::builder()
+                .src_offset(tile_bytes)
+                .size(tile_bytes)
+                .build()],
+        );
+
+        if first {
+            device.cmd_fill_buffer(
+                command_buffer,
+                resources.tile_c.buffer,
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+        }
+
+        let transfer_to_compute = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[transfer_to_compute],
+            &[],
+            &[],
+        );
+
+        device.cmd_push_constants(
+            command_buffer,
+            resources.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_const.as_bytes(),
+        );
+
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            resources.pipeline_layout,
+            0,
+            &[resources.descriptor_set],
+            &[],
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            resources.pipeline,
+        );
+
+        device.cmd_dispatch(command_buffer, group_count, group_count, 1);
+
+        if last {
+            let compute_to_transfer = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build();
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[compute_to_transfer],
+                &[],
+                &[],
+            );
+
+            device.cmd_copy_buffer(
+                command_buffer,
+                resources.tile_c.buffer,
+                resources.readback.buffer,
+                &[vk::BufferCopy::builder().size(tile_bytes).build()],
+            );
+
+            let transfer_to_host = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[transfer_to_host],
+                &[],
+                &[],
+            );
+        }
+
+        vulkan_data
+            .profiler
+            .borrow_mut()
+            .end_gpu_scope(device, command_buffer, scope_id);
+
+        device
+            .end_command_buffer(command_buffer)
+            .map_err(|_| String::from("failed to end command buffer"))
+    };
+
+    let submission = ended.and_then(|()| super::submit(vulkan_data, command_buffer));
+
+    // the scopes of a command buffer that never ran cannot be resolved
+    if submission.is_err() {
+        vulkan_data.profiler.borrow_mut().discard_gpu_scopes();
+    }
+
+    let submission = submission?;
+
+    unsafe {
+        // the staging buffers are refilled for the next step
+        device
+            .device_wait_idle()
+            .map_err(|_| String::from("failed to wait device idle"))?;
+    }
+
+    vulkan_data
+        .profiler
+        .borrow_mut()
+        .resolve_gpu_scopes(device, &submission)?;
+
+    unsafe {
+        device.free_command_buffers(vulkan_data.command_pool, &[command_buffer]);
+
+        device
+            .reset_command_pool(
+                vulkan_data.command_pool,
+                vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+            )
+            .map_err(|_| String::from("failed to reset command pool"))?;
+    }
+
+    Ok(())
+}
+
+fn multiply_tiles(
+    vulkan_data: &VulkanData,
+    resources: &PanelResources,
+    a: &[f32],
+    b: &[f32],
+    n: usize,
+    tile_size: u32,
+) -> Result<Vec<f32>, String> {
+    let device = &vulkan_data.device;
+    let tile = tile_size as usize;
+    let tile_count = n.div_ceil(tile);
+
+    let mut c = vec![0.0f32; n * n];
+
+    let upload_ptr = super::map_mem_buffer(vulkan_data, &resources.upload)?;
+
+    let readback_ptr = match super::map_mem_buffer(vulkan_data, &resources.readback) {
+        Ok(ptr) => ptr,
+        Err(msg) => {
+            unsafe {
+                device.unmap_memory(resources.upload.device_memory);
+            }
+
+            return Err(msg);
+        }
+    };
+
+    // the panels go A first, B second, both tile x tile
+    let upload =
+        unsafe { std::slice::from_raw_parts_mut(upload_ptr.cast::<f32>(), 2 * tile * tile) };
+    let readback = unsafe { std::slice::from_raw_parts(readback_ptr.cast::<f32>(), tile * tile) };
+
+    let (upload_a, upload_b) = upload.split_at_mut(tile * tile);
+
+    let mut result = Ok(());
+
+    'tiles: for tile_row in 0..tile_count {
+        for tile_col in 0..tile_count {
+            for step in 0..tile_count {
+                pack_block(a, n, tile_row * tile, step * tile, upload_a, tile);
+                pack_block(b, n, step * tile, tile_col * tile, upload_b, tile);
+
+                result = super::flush_mem_buffer(vulkan_data, &resources.upload).and_then(|_| {
+                    run_step(
+                        vulkan_data,
+                        resources,
+                        tile_size,
+                        step == 0,
+                        step + 1 == tile_count,
+                    )
+                });
+
+                if result.is_err() {
+                    break 'tiles;
+                }
+            }
+
+            result = super::invalidate_mem_buffer(vulkan_data, &resources.readback);
+
+            if result.is_err() {
+                break 'tiles;
+            }
+
+            unpack_block(readback, tile, &mut c, n, tile_row * tile, tile_col * tile);
+        }
+    }
+
+    unsafe {
+        device.unmap_memory(resources.upload.device_memory);
+        device.unmap_memory(resources.readback.device_memory);
+    }
+
+    result?;
+
+    Ok(c)
+}
+
+// C = A * B for n x n row major matrices kept in host memory; tiles of C are accumulated on the
+// device from panels of A and B streamed in one step at a time, so only three tiles are resident
+pub fn multiply_out_of_core(
+    vulkan_data: &VulkanData,
+    a: &[f32],
+    b: &[f32],
+    n: usize,
+) -> Result<Vec<f32>, String> {
+    let _scope = vulkan_data.cpu_scope("multiply out of core");
+
+    if a.len() != n * n || b.len() != n * n {
+        return Err(format!("the matrices are not {} x {}", n, n));
+    }
+
+    let memory_budget = vulkan_data.memory_budget();
+
+    let tile_size =
+        out_of_core_tile_size(&memory_budget, &vulkan_data.device_capabilities.limits, n)
+            .ok_or_else(|| {
+                format!(
+                    "not even a {0} x {0} tile fits in the device memory\n{1}",
+                    constants::WORKGROUP_SIZE,
+                    memory_budget
+                )
+            })?;
+
+    log::info!(
+        "multiplying {} x {} matrices out of core with {} x {} tiles",
+        n,
+        n,
+        tile_size,
+        tile_size
+    );
+
+    let resources = PanelResources::new(vulkan_data, tile_size)?;
+
+    let c = multiply_tiles(vulkan_data, &resources, a, b, n, tile_size);
+
+    resources.clean(&vulkan_data.device);
+
+    vulkan_data.check_validation_errors()?;
+
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> vk::PhysicalDeviceLimits {
+        vk::PhysicalDeviceLimits {
+            max_compute_work_group_count: [65535; 3],
+            max_storage_buffer_range: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    fn matrix(n: usize) -> Vec<f32> {
+        (0..n * n).map(|i| i as f32 + 1.0).collect()
+    }
+
+    #[test]
+    fn pack_block_pads_past_the_matrix() {
+        let n = 5;
+        let a = matrix(n);
+        let mut tile = vec![-1.0f32; 16];
+
+        pack_block(&a, n, 4, 4, &mut tile, 4);
+
+        let mut expected = vec![0.0f32; 16];
+        expected[0] = a[4 * n + 4];
+
+        assert_eq!(tile, expected);
+    }
+
+    #[test]
+    fn pack_block_tile_larger_than_matrix() {
+        let n = 3;
+        let a = matrix(n);
+        let mut tile = vec![-1.0f32; 16];
+
+        pack_block(&a, n, 0, 0, &mut tile, 4);
+
+        for r in 0..4 {
+            for c in 0..4 {
+                let expected = match r < n && c < n {
+                    true => a[r * n + c],
+                    false => 0.0,
+                };
+
+                assert_eq!(tile[r * 4 + c], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn unpack_block_round_trips_partial_tiles() {
+        let n = 7;
+        let tile_size = 4;
+        let a = matrix(n);
+
+        let mut result = vec![0.0f32; n * n];
+        let mut tile = vec![0.0f32; tile_size * tile_size];
+
+        for row in (0..n).step_by(tile_size) {
+            for col in (0..n).step_by(tile_size) {
+                pack_block(&a, n, row, col, &mut tile, tile_size);
+                unpack_block(&tile, tile_size, &mut result, n, row, col);
+            }
+        }
+
+        assert_eq!(result, a);
+    }
+
+    #[test]
+    fn unpack_block_tile_larger_than_matrix() {
+        let n = 3;
+        let tile = (0..16).map(|i| i as f32).collect::<Vec<_>>();
+        let mut result = vec![0.0f32; n * n];
+
+        unpack_block(&tile, 4, &mut result, n, 0, 0);
+
+        assert_eq!(result, [0.0, 1.0, 2.0, 4.0, 5.0, 6.0, 8.0, 9.0, 10.0]);
+    }
+
+    #[test]
+    fn tile_size_is_padded_to_the_workgroup_size() {
+        let budget = MemoryBudget::device_local(vk::DeviceSize::MAX / 4);
+
+        let workgroup_size = constants::WORKGROUP_SIZE;
+
+        // n smaller than one workgroup still gets a full tile
+        assert_eq!(
+            out_of_core_tile_size(&budget, &limits(), 5),
+            Some(workgroup_size)
+        );
+        assert_eq!(
+            out_of_core_tile_size(&budget, &limits(), workgroup_size as usize + 1),
+            Some(2 * workgroup_size)
+        );
+    }
+
+    #[test]
+    fn tile_size_halves_until_it_fits_twice() {
+        let workgroup_size = constants::WORKGROUP_SIZE;
+        let n = 4 * workgroup_size as usize;
+
+        let budget = MemoryBudget::device_local(2 * device_size(2 * workgroup_size));
+        assert_eq!(
+            out_of_core_tile_size(&budget, &limits(), n),
+            Some(2 * workgroup_size)
+        );
+
+        let budget = MemoryBudget::device_local(2 * device_size(2 * workgroup_size) - 1);
+        assert_eq!(
+            out_of_core_tile_size(&budget, &limits(), n),
+            Some(workgroup_size)
+        );
+
+        let budget = MemoryBudget::device_local(2 * device_size(workgroup_size) - 1);
+        assert_eq!(out_of_core_tile_size(&budget, &limits(), n), None);
+    }
+
+    #[test]
+    fn tile_size_respects_the_dispatch_limits() {
+        let budget = MemoryBudget::device_local(vk::DeviceSize::MAX / 4);
+
+        let limits = vk::PhysicalDeviceLimits {
+            max_compute_work_group_count: [1, 1, 1],
+            ..limits()
+        };
+
+        assert_eq!(
+            out_of_core_tile_size(&budget, &limits, 1000),
+            Some(constants::WORKGROUP_SIZE)
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use ash::extensions::khr;
+use ash::vk;
+
+// how long to wait for the profiling lock, in ns
+const PROFILING_LOCK_TIMEOUT: u64 = 1000000000;
+
+#[derive(Clone, Debug)]
+pub struct PerformanceCounterValue {
+    pub name: String,
+    pub category: String,
+    pub unit: &'static str,
+    pub value: f64,
+}
+
+struct SelectedCounter {
+    index: u32,
+    name: String,
+    category: String,
+    unit: vk::PerformanceCounterUnitKHR,
+    storage: vk::PerformanceCounterStorageKHR,
+}
+
+fn unit_name(unit: vk::PerformanceCounterUnitKHR) -> &'static str {
+    match unit {
+        vk::PerformanceCounterUnitKHR::GENERIC => "",
+        vk::PerformanceCounterUnitKHR::PERCENTAGE => "%",
+        vk::PerformanceCounterUnitKHR::NANOSECONDS => "ns",
+        vk::PerformanceCounterUnitKHR::BYTES => "B",
+        vk::PerformanceCounterUnitKHR::BYTES_PER_SECOND => "B/s",
+        vk::PerformanceCounterUnitKHR::KELVIN => "K",
+        vk::PerformanceCounterUnitKHR::WATTS => "W",
+        vk::PerformanceCounterUnitKHR::VOLTS => "V",
+        vk::PerformanceCounterUnitKHR::AMPS => "A",
+        vk::PerformanceCounterUnitKHR::HERTZ => "Hz",
+        vk::PerformanceCounterUnitKHR::CYCLES => "cycles",
+        _ => "?",
+    }
+}
+
+pub fn c_str_to_string(chars: &[std::os::raw::c_char]) -> String {
+    unsafe { std::ffi::CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// vendor hardware counters through VK_KHR_performance_query, limited to the counters that
+// fit in a single pass so a dispatch does not have to be replayed
+pub struct PerformanceCounters {
+    performance_query: khr::PerformanceQuery,
+    query_pool: vk::QueryPool,
+    counters: Vec<SelectedCounter>,
+}
+
+impl PerformanceCounters {
+    // holds the profiling lock until `clean`
+    pub fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        queue_family: u32,
+    ) -> Result<Self, String> {
+        log::info!("creating performance counters");
+
+        let performance_query = khr::PerformanceQuery::new(entry, instance);
+
+        let count = unsafe {
+            performance_query
+                .enumerate_physical_device_queue_family_performance_query_counters_len(
+                    physical_device,
+                    queue_family,
+                )
+                .map_err(|_| String::from("failed to enumerate performance counters"))?
+        };
+
+        let mut counters = vec![vk::PerformanceCounterKHR::default(); count];
+        let mut descriptions = vec![vk::PerformanceCounterDescriptionKHR::default(); count];
+
+        unsafe {
+            performance_query
+                .enumerate_physical_device_queue_family_performance_query_counters(
+                    physical_device,
+                    queue_family,
+                    &mut counters,
+                    &mut descriptions,
+                )
+                .map_err(|_| String::from("failed to enumerate performance counters"))?;
+        }
+
+        // counters scoped to a command buffer or render pass constrain the recording, only the
+        // ones scoped to single commands are used; each is kept if the set still fits a pass
+        let mut selected = Vec::new();
+        let mut indices = Vec::new();
+
+        for (index, (counter, description)) in counters.iter().zip(&descriptions).enumerate() {
+            if counter.scope != vk::PerformanceCounterScopeKHR::COMMAND {
+                continue;
+            }
+
+            indices.push(index as u32);
+
+            let create_info = vk::QueryPoolPerformanceCreateInfoKHR::builder()
+                .queue_family_index(queue_family)
+                .counter_indices(&indices)
+                .build();
+
+            let passes = unsafe {
+                performance_query.get_physical_device_queue_family_performance_query_passes(
+                    physical_device,
+                    &create_info,
+                )
+            };
+
+            if passes != 1 {
+                indices.pop();
+                continue;
+            }
+
+            selected.push(SelectedCounter {
+                index: index as u32,
+                name: c_str_to_string(&description.name),
+                category: c_str_to_string(&description.category),
+                unit: counter.unit,
+                storage: counter.storage,
+            });
+        }
+
+        log::info!(
+            "using {} of {} performance counters: {:?}",
+            selected.len(),
+            count,
+            selected
+                .iter()
+                .map(|counter| counter.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        if selected.is_empty() {
+            return Err(String::from("no performance counter fits a single pass"));
+        }
+
+        let counter_indices = selected
+            .iter()
+            .map(|counter| counter.index)
+            .collect::<Vec<_>>();
+
+        let mut performance_create_info = vk::QueryPoolPerformanceCreateInfoKHR::builder()
+            .queue_family_index(queue_family)
+            .counter_indices(&counter_indices)
+            .build();
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PERFORMANCE_QUERY_KHR)
+            .query_count(1)
+            .push_next(&mut performance_create_info)
+            .build();
+
+        let query_pool = unsafe {
+            device
+                .create_query_pool(&create_info, None)
+                .map_err(|_| String::from("failed to create performance query pool"))?
+        };
+
+        let lock_info = vk::AcquireProfilingLockInfoKHR::builder()
+            .timeout(PROFILING_LOCK_TIMEOUT)
+            .build();
+
+        let lock_result =
+            unsafe { performance_query.acquire_profiling_lock(device.handle(), &lock_info) };
+
+        if lock_result.is_err() {
+            unsafe {
+                device.destroy_query_pool(query_pool, None);
+            }
+
+            return Err(String::from("failed to acquire the profiling lock"));
+        }
+
+        Ok(Self {
+            performance_query,
+            query_pool,
+            counters: selected,
+        })
+    }
+
+    // performance queries cannot be reset in the command buffer that uses them
+    pub fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.reset_query_pool(self.query_pool, 0, 1);
+
+            device.cmd_begin_query(
+                command_buffer,
+                self.query_pool,
+                0,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+    }
+
+    pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_end_query(command_buffer, self.query_pool, 0);
+        }
+    }
+
+    pub fn read(&self, device: &ash::Device) -> Result<Vec<PerformanceCounterValue>, String> {
+        let mut results = vec![vk::PerformanceCounterResultKHR::default(); self.counters.len()];
+
+        // one query holds a result per counter, which Device::get_query_pool_results cannot size
+        let size = std::mem::size_of_val(results.as_slice());
+
+        unsafe {
+            (device.fp_v1_0().get_query_pool_results)(
+                device.handle(),
+                self.query_pool,
+                0,
+                1,
+                size,
+                results.as_mut_ptr().cast(),
+                size as vk::DeviceSize,
+                vk::QueryResultFlags::WAIT,
+            )
+            .result()
+            .map_err(|_| String::from("failed to get performance query results"))?;
+        }
+
+        let values = self
+            .counters
+            .iter()
+            .zip(&results)
+            .map(|(counter, result)| {
+                let value = unsafe {
+                    match counter.storage {
+                        vk::PerformanceCounterStorageKHR::INT32 => result.int32 as f64,
+                        vk::PerformanceCounterStorageKHR::INT64 => result.int64 as f64,
+                        vk::PerformanceCounterStorageKHR::UINT32 => result.uint32 as f64,
+                        vk::PerformanceCounterStorageKHR::UINT64 => result.uint64 as f64,
+                        vk::PerformanceCounterStorageKHR::FLOAT32 => result.float32 as f64,
+                        _ => result.float64,
+                    }
+                };
+
+                PerformanceCounterValue {
+                    name: counter.name.clone(),
+                    category: counter.category.clone(),
+                    unit: unit_name(counter.unit),
+                    value,
+                }
+            })
+            .collect();
+
+        Ok(values)
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        log::info!("cleaning performance counters");
+
+        unsafe {
+            self.performance_query
+                .release_profiling_lock(device.handle());
+
+            device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
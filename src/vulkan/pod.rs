@@ -0,0 +1,93 @@
+/// Plain old data the buffers are filled with and read into: no padding, no pointers and every
+/// bit pattern is a valid value; half precision values travel as their u16 bits.
+///
+/// # Safety
+/// Implement it only for `#[repr(C)]` or `#[repr(transparent)]` types made of Pod fields without
+/// padding between them.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub fn pod_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), std::mem::size_of_val(data)) }
+}
+
+pub fn pod_bytes_mut<T: Pod>(data: &mut [T]) -> &mut [u8] {
+    unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr().cast::<u8>(), std::mem::size_of_val(data))
+    }
+}
+
+// whether `size` bytes of `T` fit at `offset` into a buffer of `buffer_size` bytes, with the
+// elements aligned in the mapped memory
+pub fn check_transfer_range<T: Pod>(
+    offset: ash::vk::DeviceSize,
+    size: ash::vk::DeviceSize,
+    buffer_size: ash::vk::DeviceSize,
+) -> Result<(), String> {
+    let alignment = std::mem::align_of::<T>() as ash::vk::DeviceSize;
+
+    if !offset.is_multiple_of(alignment) {
+        return Err(format!(
+            "offset {} is not aligned to the {} byte alignment of {}",
+            offset,
+            alignment,
+            std::any::type_name::<T>()
+        ));
+    }
+
+    if offset.checked_add(size).is_none_or(|end| end > buffer_size) {
+        return Err(format!(
+            "{} bytes at offset {} exceed the buffer of {} bytes",
+            size, offset, buffer_size
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_ranges_inside_the_buffer() {
+        assert!(check_transfer_range::<f32>(0, 16, 16).is_ok());
+        assert!(check_transfer_range::<f32>(8, 8, 16).is_ok());
+        assert!(check_transfer_range::<u8>(3, 1, 4).is_ok());
+    }
+
+    #[test]
+    fn rejects_misaligned_offsets() {
+        assert!(check_transfer_range::<f32>(2, 4, 16).is_err());
+        assert!(check_transfer_range::<f64>(4, 8, 16).is_err());
+        assert!(check_transfer_range::<u16>(1, 2, 16).is_err());
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end() {
+        assert!(check_transfer_range::<f32>(0, 20, 16).is_err());
+        assert!(check_transfer_range::<f32>(16, 4, 16).is_err());
+        assert!(check_transfer_range::<f32>(20, 0, 16).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_ranges() {
+        assert!(check_transfer_range::<u8>(u64::MAX, 1, u64::MAX).is_err());
+        assert!(check_transfer_range::<f32>(4, u64::MAX - 2, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn accepts_zero_length_transfers_up_to_the_end() {
+        assert!(check_transfer_range::<f32>(0, 0, 0).is_ok());
+        assert!(check_transfer_range::<f32>(16, 0, 16).is_ok());
+    }
+}
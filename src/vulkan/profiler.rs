@@ -0,0 +1,265 @@
+use ash::vk;
+
+use std::cell::RefCell;
+use std::time::Instant;
+
+use super::{GpuProfiler, GpuScopeId, GpuSpan, Submission, TimestampCalibrator};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeline {
+    Cpu,
+    Gpu,
+    // submitted work waiting for the GPU, only known with calibrated timestamps
+    Queue,
+}
+
+// one submission on the host timeline, in ms; values can be slightly negative within the
+// calibration uncertainty
+#[derive(Clone, Copy, Debug)]
+pub struct SubmissionTiming {
+    // from the call to vkQueueSubmit until the GPU started the first scope
+    pub submission_latency_ms: f64,
+    // from the return of vkQueueSubmit until the GPU started the first scope
+    pub queue_wait_ms: f64,
+    // from the start of the first scope to the end of the last one
+    pub execution_ms: f64,
+    // from the end of the last scope until the host saw the work complete
+    pub completion_latency_ms: f64,
+}
+
+// a finished scope on the common timeline, in us since the profiler was created
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+    pub name: String,
+    pub timeline: Timeline,
+    pub start_us: f64,
+    pub duration_us: f64,
+}
+
+// CPU spans and GPU scopes on one timeline, exported as a Chrome trace
+pub struct Profiler {
+    epoch: Instant,
+    gpu_profiler: GpuProfiler,
+    timestamp_calibrator: Option<TimestampCalibrator>,
+    events: Vec<TraceEvent>,
+    last_submission_timing: Option<SubmissionTiming>,
+}
+
+// records a CPU span from its creation until it is dropped
+pub struct CpuScope<'a> {
+    profiler: &'a RefCell<Profiler>,
+    name: String,
+    start: Instant,
+}
+
+impl Drop for CpuScope<'_> {
+    fn drop(&mut self) {
+        let mut profiler = self.profiler.borrow_mut();
+
+        let start_us = profiler.us_since_epoch(self.start);
+
+        profiler.events.push(TraceEvent {
+            name: std::mem::take(&mut self.name),
+            timeline: Timeline::Cpu,
+            start_us,
+            duration_us: self.start.elapsed().as_secs_f64() * 1000000.0,
+        });
+    }
+}
+
+impl Profiler {
+    pub fn new(
+        gpu_profiler: GpuProfiler,
+        timestamp_calibrator: Option<TimestampCalibrator>,
+    ) -> Self {
+        Self {
+            epoch: Instant::now(),
+            gpu_profiler,
+            timestamp_calibrator,
+            events: Vec::new(),
+            last_submission_timing: None,
+        }
+    }
+
+    fn us_since_epoch(&self, instant: Instant) -> f64 {
+        match instant.checked_duration_since(self.epoch) {
+            Some(duration) => duration.as_secs_f64() * 1000000.0,
+            None => -(self.epoch.duration_since(instant).as_secs_f64() * 1000000.0),
+        }
+    }
+
+    pub fn cpu_scope<'a>(profiler: &'a RefCell<Profiler>, name: &str) -> CpuScope<'a> {
+        CpuScope {
+            profiler,
+            name: String::from(name),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn timestamps_supported(&self) -> bool {
+        self.gpu_profiler.timestamps_supported()
+    }
+
+    pub fn begin_gpu_scope(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+    ) -> Result<GpuScopeId, String> {
+        self.gpu_profiler.begin_scope(device, command_buffer, name)
+    }
+
+    pub fn end_gpu_scope(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        scope_id: GpuScopeId,
+    ) {
+        self.gpu_profiler
+            .end_scope(device, command_buffer, scope_id);
+    }
+
+    // after an error recording or submitting the command buffer of the pending GPU scopes
+    pub fn discard_gpu_scopes(&mut self) {
+        self.gpu_profiler.discard_pending();
+    }
+
+    // reads back the GPU scopes of a submission that has completed and places them on the host
+    // timeline; without calibrated timestamps the GPU clock is not related to the host one, so
+    // the earliest scope is placed at the submission
+    pub fn resolve_gpu_scopes(
+        &mut self,
+        device: &ash::Device,
+        submission: &Submission,
+    ) -> Result<Vec<GpuSpan>, String> {
+        let completed_at = Instant::now();
+
+        let spans = self.gpu_profiler.resolve(device)?;
+
+        if spans.is_empty() {
+            self.last_submission_timing = None;
+            return Ok(spans);
+        }
+
+        let gpu_start_ns = spans
+            .iter()
+            .map(|span| span.start_ns)
+            .fold(f64::INFINITY, f64::min);
+        let gpu_end_ns = spans
+            .iter()
+            .map(|span| span.end_ns)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let calibration = match &self.timestamp_calibrator {
+            Some(timestamp_calibrator) => Some(timestamp_calibrator.calibrate()?),
+            None => None,
+        };
+
+        // host timeline position of a GPU time in ns
+        let gpu_origin_us = match &calibration {
+            Some(calibration) => self.us_since_epoch(calibration.instant_at(gpu_start_ns)),
+            None => self.us_since_epoch(submission.submitted_at),
+        };
+        let to_us = |gpu_ns: f64| gpu_origin_us + (gpu_ns - gpu_start_ns) / 1000.0;
+
+        for span in &spans {
+            self.events.push(TraceEvent {
+                name: span.name.clone(),
+                timeline: Timeline::Gpu,
+                start_us: to_us(span.start_ns),
+                duration_us: (span.end_ns - span.start_ns) / 1000.0,
+            });
+        }
+
+        self.last_submission_timing = match calibration {
+            Some(calibration) => {
+                log::info!(
+                    "calibration uncertainty {} us",
+                    calibration.uncertainty.as_secs_f64() * 1000000.0
+                );
+
+                let submitted_us = self.us_since_epoch(submission.submitted_at);
+                let returned_us = self.us_since_epoch(submission.returned_at);
+                let completed_us = self.us_since_epoch(completed_at);
+                let gpu_end_us = to_us(gpu_end_ns);
+
+                self.events.push(TraceEvent {
+                    name: String::from("queued"),
+                    timeline: Timeline::Queue,
+                    start_us: returned_us,
+                    duration_us: (gpu_origin_us - returned_us).max(0.0),
+                });
+
+                Some(SubmissionTiming {
+                    submission_latency_ms: (gpu_origin_us - submitted_us) / 1000.0,
+                    queue_wait_ms: (gpu_origin_us - returned_us) / 1000.0,
+                    execution_ms: (gpu_end_us - gpu_origin_us) / 1000.0,
+                    completion_latency_ms: (completed_us - gpu_end_us) / 1000.0,
+                })
+            }
+            None => None,
+        };
+
+        Ok(spans)
+    }
+
+    // timing of the last resolved submission, None without calibrated timestamps
+    pub fn last_submission_timing(&self) -> Option<SubmissionTiming> {
+        self.last_submission_timing
+    }
+
+    // Chrome trace event format, viewable in chrome://tracing or Perfetto
+    pub fn write_chrome_trace(&self, path: &std::path::Path) -> Result<(), String> {
+        log::info!("writing chrome trace to {:?}", path);
+
+        let thread_names = [
+            (Timeline::Cpu, "CPU"),
+            (Timeline::Gpu, "GPU"),
+            (Timeline::Queue, "queue wait"),
+        ];
+
+        let mut trace_events = thread_names
+            .iter()
+            .map(|(timeline, name)| {
+                serde_json::json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": 0,
+                    "tid": *timeline as u32,
+                    "args": { "name": name },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for event in &self.events {
+            trace_events.push(serde_json::json!({
+                "name": event.name,
+                "cat": match event.timeline {
+                    Timeline::Cpu => "cpu",
+                    Timeline::Gpu => "gpu",
+                    Timeline::Queue => "queue",
+                },
+                "ph": "X",
+                "pid": 0,
+                "tid": event.timeline as u32,
+                "ts": event.start_us,
+                "dur": event.duration_us,
+            }));
+        }
+
+        let trace = serde_json::json!({
+            "traceEvents": trace_events,
+            "displayTimeUnit": "ms",
+        });
+
+        let trace_str = serde_json::to_string(&trace)
+            .map_err(|_| String::from("failed to serialize chrome trace"))?;
+
+        std::fs::write(path, trace_str)
+            .map_err(|_| format!("failed to write chrome trace {:?}", path))
+    }
+
+    pub fn clean(self, device: &ash::Device) {
+        self.gpu_profiler.clean(device);
+    }
+}
@@ -1,25 +1,50 @@
-use crate::{constants, vulkan::MemBuffer};
+use crate::vulkan::MemBuffer;
 use ash::vk;
 
-use super::VulkanData;
+use super::{HostAccess, Pod, VulkanData};
 
-pub fn read_data_from_buffer(
+// reads `len` elements from `offset` bytes into the buffer
+pub fn read_data_from_buffer<T: Pod>(
     vulkan_data: &VulkanData,
     mem_buffer: &MemBuffer,
-    size: vk::DeviceSize,
-) -> Result<Vec<f32>, String> {
-    assert!(size <= mem_buffer.size);
+    offset: vk::DeviceSize,
+    len: usize,
+) -> Result<Vec<T>, String> {
+    // every bit pattern is a valid Pod value
+    let mut data = vec![unsafe { std::mem::zeroed::<T>() }; len];
+
+    read_data_from_buffer_into(vulkan_data, mem_buffer, offset, &mut data)?;
+
+    Ok(data)
+}
+
+// fills `data` from `offset` bytes into the buffer, without allocating on the host
+pub fn read_data_from_buffer_into<T: Pod>(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+    offset: vk::DeviceSize,
+    data: &mut [T],
+) -> Result<(), String> {
+    let _scope = vulkan_data.cpu_scope("readback");
+
+    let size = std::mem::size_of_val(data) as vk::DeviceSize;
+    super::check_transfer_range::<T>(offset, size, mem_buffer.size)?;
+
+    if size == 0 {
+        return Ok(());
+    }
+
+    // the dispatch made the shader writes available to the host, the mapping makes them visible
+    if mem_buffer.host_mapped() {
+        return read_mapped(vulkan_data, mem_buffer, offset, data);
+    }
 
     // create staging buffer
-    let staging_mem_buffer = super::create_mem_buffer(
-        &vulkan_data.instance,
-        vulkan_data.physical_device,
-        &vulkan_data.device,
+    let staging_mem_buffer = super::create_host_mem_buffer(
+        vulkan_data,
         size,
         vk::BufferUsageFlags::TRANSFER_DST,
-        vk::MemoryPropertyFlags::HOST_VISIBLE
-            | vk::MemoryPropertyFlags::HOST_COHERENT
-            | vk::MemoryPropertyFlags::HOST_CACHED,
+        HostAccess::Readback,
     )?;
 
     // allocate command buffer
@@ -29,9 +54,18 @@ pub fn read_data_from_buffer(
     super::begin_command_buffer(vulkan_data, command_buffer)?;
 
     // copy data to device local buffer
-    let buffer_copy = vk::BufferCopy::builder().size(size).build();
+    let buffer_copy = vk::BufferCopy::builder()
+        .src_offset(offset)
+        .size(size)
+        .build();
 
-    unsafe {
+    let scope_id = vulkan_data.profiler.borrow_mut().begin_gpu_scope(
+        &vulkan_data.device,
+        command_buffer,
+        "readback",
+    )?;
+
+    let ended = unsafe {
         vulkan_data.device.cmd_copy_buffer(
             command_buffer,
             mem_buffer.buffer,
@@ -39,14 +73,42 @@ pub fn read_data_from_buffer(
             &[buffer_copy],
         );
 
+        let transfer_to_host = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build();
+
+        vulkan_data.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[transfer_to_host],
+            &[],
+            &[],
+        );
+
+        vulkan_data.profiler.borrow_mut().end_gpu_scope(
+            &vulkan_data.device,
+            command_buffer,
+            scope_id,
+        );
+
         vulkan_data
             .device
             .end_command_buffer(command_buffer)
-            .map_err(|_| String::from("failed to end command buffer"))?
-    }
+            .map_err(|_| String::from("failed to end command buffer"))
+    };
 
     // submit
-    super::submit(vulkan_data, command_buffer)?;
+    let submission = ended.and_then(|()| super::submit(vulkan_data, command_buffer));
+
+    // the scopes of a command buffer that never ran cannot be resolved
+    if submission.is_err() {
+        vulkan_data.profiler.borrow_mut().discard_gpu_scopes();
+    }
+
+    let submission = submission?;
 
     // wait
     unsafe {
@@ -57,6 +119,11 @@ pub fn read_data_from_buffer(
             .map_err(|_| String::from("failed to wait device idle"))?;
     }
 
+    vulkan_data
+        .profiler
+        .borrow_mut()
+        .resolve_gpu_scopes(&vulkan_data.device, &submission)?;
+
     // clean
     unsafe {
         // free command buffer
@@ -75,25 +142,9 @@ pub fn read_data_from_buffer(
     }
 
     // read the data back
-    unsafe {
-        let mapped_data_ptr = vulkan_data
-            .device
-            .map_memory(
-                staging_mem_buffer.device_memory,
-                0,
-                staging_mem_buffer.size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .map_err(|_| String::from("failed to map buffer memory"))?;
-
-        let data =
-            std::slice::from_raw_parts(mapped_data_ptr.cast::<f32>(), constants::N * constants::N)
-                .to_owned();
-
-        vulkan_data
-            .device
-            .unmap_memory(staging_mem_buffer.device_memory);
+    let result = read_mapped(vulkan_data, &staging_mem_buffer, 0, data);
 
+    unsafe {
         // destroy buffer
         vulkan_data
             .device
@@ -103,7 +154,30 @@ pub fn read_data_from_buffer(
         vulkan_data
             .device
             .free_memory(staging_mem_buffer.device_memory, None);
+    }
+
+    result
+}
+
+fn read_mapped<T: Pod>(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+    offset: vk::DeviceSize,
+    data: &mut [T],
+) -> Result<(), String> {
+    let mapped_data_ptr = super::map_mem_buffer(vulkan_data, mem_buffer)?;
 
-        Ok(data)
+    let bytes = super::pod_bytes_mut(data);
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            mapped_data_ptr.cast::<u8>().add(offset as usize),
+            bytes.as_mut_ptr(),
+            bytes.len(),
+        );
+
+        vulkan_data.device.unmap_memory(mem_buffer.device_memory);
     }
+
+    Ok(())
 }
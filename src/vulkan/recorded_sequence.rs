@@ -0,0 +1,215 @@
+use ash::vk;
+
+use crate::shader_interface::PushConst;
+
+use super::VulkanData;
+
+// fills the command buffer with the given push constants and returns the number of barriers it
+// recorded
+type Record<'a> = Box<dyn Fn(vk::CommandBuffer, &PushConst) -> Result<usize, String> + 'a>;
+
+// a command buffer recorded once and submitted many times; it owns its command pool, as the
+// shared one is reset after every dispatch, and a fence that tells whether the last submission
+// is still running. the buffers stay bound by reference, so their contents can change between
+// submissions once `wait` returned; release it with `clean`.
+// vulkan_data stays borrowed while the sequence lives, the pipeline it was recorded with cannot
+// be replaced under it. push constants are part of the recorded commands, `set_push_constants`
+// records the sequence again with the new values
+pub struct RecordedSequence<'a> {
+    vulkan_data: &'a VulkanData,
+    record: Record<'a>,
+    push_const: PushConst,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    // false after a failed recording, the command buffer must not be submitted
+    recorded: bool,
+    in_flight: bool,
+    pub task_count: usize,
+    pub barrier_count: usize,
+}
+
+impl<'a> RecordedSequence<'a> {
+    pub fn new<F>(
+        vulkan_data: &'a VulkanData,
+        task_count: usize,
+        push_const: PushConst,
+        record: F,
+    ) -> Result<Self, String>
+    where
+        F: Fn(vk::CommandBuffer, &PushConst) -> Result<usize, String> + 'a,
+    {
+        log::info!("creating recorded sequence of {} tasks", task_count);
+
+        let command_pool =
+            super::create_command_pool(&vulkan_data.device, vulkan_data.queue_family)?;
+
+        let mut sequence = Self {
+            vulkan_data,
+            record: Box::new(record),
+            push_const,
+            command_pool,
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            recorded: false,
+            in_flight: false,
+            task_count,
+            barrier_count: 0,
+        };
+
+        if let Err(msg) = sequence.create().and_then(|_| sequence.record()) {
+            sequence.clean();
+            return Err(msg);
+        }
+
+        Ok(sequence)
+    }
+
+    fn create(&mut self) -> Result<(), String> {
+        let device = &self.vulkan_data.device;
+
+        self.fence = unsafe {
+            device
+                .create_fence(&vk::FenceCreateInfo::builder().build(), None)
+                .map_err(|_| String::from("failed to create fence"))?
+        };
+
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+            .build();
+
+        self.command_buffer = unsafe {
+            device
+                .allocate_command_buffers(&allocate_info)
+                .map_err(|_| String::from("failed to allocate command buffer"))?[0]
+        };
+
+        Ok(())
+    }
+
+    fn record(&mut self) -> Result<(), String> {
+        let device = &self.vulkan_data.device;
+
+        self.recorded = false;
+
+        // no one time submit, the buffer is submitted again after each wait
+        unsafe {
+            device
+                .begin_command_buffer(
+                    self.command_buffer,
+                    &vk::CommandBufferBeginInfo::builder().build(),
+                )
+                .map_err(|_| String::from("failed to begin command buffer"))?;
+        }
+
+        self.barrier_count = (self.record)(self.command_buffer, &self.push_const)?;
+
+        unsafe {
+            device
+                .end_command_buffer(self.command_buffer)
+                .map_err(|_| String::from("failed to end command buffer"))?;
+        }
+
+        self.recorded = true;
+
+        Ok(())
+    }
+
+    // waits for a submission in flight, the command buffer cannot be reset while it is pending
+    pub fn set_push_constants(&mut self, push_const: PushConst) -> Result<(), String> {
+        log::info!("recording sequence again with {:?}", push_const);
+
+        self.wait()?;
+
+        self.push_const = push_const;
+
+        unsafe {
+            self.vulkan_data
+                .device
+                .reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())
+                .map_err(|_| String::from("failed to reset command pool"))?;
+        }
+
+        self.record()
+    }
+
+    // a submission still in flight is waited for first, the command buffer may only be pending
+    // once
+    pub fn submit(&mut self) -> Result<(), String> {
+        let _scope = self.vulkan_data.cpu_scope("recorded sequence");
+
+        if !self.recorded {
+            return Err(String::from("recording the sequence failed"));
+        }
+
+        self.wait()?;
+
+        let device = &self.vulkan_data.device;
+
+        let cmd_buffers = [self.command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&cmd_buffers)
+            .build();
+
+        unsafe {
+            device
+                .reset_fences(&[self.fence])
+                .map_err(|_| String::from("failed to reset fence"))?;
+
+            device
+                .queue_submit(self.vulkan_data.queue, &[submit_info], self.fence)
+                .map_err(|_| String::from("failed to submit recorded sequence"))?;
+        }
+
+        self.in_flight = true;
+
+        Ok(())
+    }
+
+    pub fn is_in_flight(&mut self) -> Result<bool, String> {
+        if self.in_flight {
+            self.in_flight = !unsafe {
+                self.vulkan_data
+                    .device
+                    .get_fence_status(self.fence)
+                    .map_err(|_| String::from("failed to get fence status"))?
+            };
+        }
+
+        Ok(self.in_flight)
+    }
+
+    // after this the host may read the results and change the buffer contents
+    pub fn wait(&mut self) -> Result<(), String> {
+        if !self.in_flight {
+            return Ok(());
+        }
+
+        unsafe {
+            self.vulkan_data
+                .device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .map_err(|_| String::from("failed to wait for fence"))?;
+        }
+
+        self.in_flight = false;
+
+        Ok(())
+    }
+
+    pub fn clean(mut self) {
+        if let Err(msg) = self.wait() {
+            log::error!("{}", msg);
+        }
+
+        let device = &self.vulkan_data.device;
+
+        unsafe {
+            // destroying the pool frees the command buffer
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_fence(self.fence, None);
+        }
+    }
+}
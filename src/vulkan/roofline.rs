@@ -0,0 +1,346 @@
+use crate::constants;
+use crate::shader_variants::ShaderVariant;
+
+use super::{KernelConfig, VulkanData};
+
+const SVG_WIDTH: f64 = 800.0;
+const SVG_HEIGHT: f64 = 500.0;
+const SVG_MARGIN: f64 = 70.0;
+
+// a kernel timed on the bound buffers
+#[derive(Clone, Debug)]
+pub struct RooflinePoint {
+    pub name: String,
+    pub flops: f64,
+    // compulsory traffic, every input read and every output written once; caches and tiling make
+    // the actual traffic differ
+    pub bytes: f64,
+    pub gpu_time_ms: f32,
+}
+
+impl RooflinePoint {
+    // FLOP per byte
+    pub fn arithmetic_intensity(&self) -> f64 {
+        self.flops / self.bytes
+    }
+
+    pub fn flops_per_second(&self) -> f64 {
+        self.flops / (self.gpu_time_ms as f64 / 1000.0)
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes / (self.gpu_time_ms as f64 / 1000.0)
+    }
+}
+
+// peaks estimated by the micro-benchmarks and where each kernel sits under them
+#[derive(Debug)]
+pub struct Roofline {
+    pub peak_bytes_per_second: f64,
+    pub peak_flops_per_second: f64,
+    pub points: Vec<RooflinePoint>,
+}
+
+impl Roofline {
+    // the arithmetic intensity from which a kernel can be compute bound
+    pub fn ridge_point(&self) -> f64 {
+        self.peak_flops_per_second / self.peak_bytes_per_second
+    }
+
+    pub fn attainable_flops_per_second(&self, arithmetic_intensity: f64) -> f64 {
+        (arithmetic_intensity * self.peak_bytes_per_second).min(self.peak_flops_per_second)
+    }
+
+    pub fn write_svg(&self, path: &std::path::Path) -> Result<(), String> {
+        log::info!("writing roofline chart to {:?}", path);
+
+        std::fs::write(path, self.svg())
+            .map_err(|_| format!("failed to write roofline chart {:?}", path))
+    }
+
+    // log-log chart of FLOP/s over arithmetic intensity, the axes span whole decades
+    fn svg(&self) -> String {
+        let ridge_point = self.ridge_point();
+
+        let points = self
+            .points
+            .iter()
+            .filter(|point| point.flops > 0.0)
+            .collect::<Vec<_>>();
+
+        let min_intensity = points
+            .iter()
+            .map(|point| point.arithmetic_intensity())
+            .fold(ridge_point / 100.0, f64::min);
+        let max_intensity = points
+            .iter()
+            .map(|point| point.arithmetic_intensity())
+            .fold(ridge_point * 100.0, f64::max);
+
+        let x_min = min_intensity.log10().floor();
+        let x_max = max_intensity.log10().ceil();
+
+        let min_flops = points.iter().map(|point| point.flops_per_second()).fold(
+            self.attainable_flops_per_second(10f64.powf(x_min)),
+            f64::min,
+        );
+
+        let y_min = min_flops.log10().floor();
+        let y_max = (self.peak_flops_per_second * 2.0).log10().ceil();
+
+        let plot_width = SVG_WIDTH - 2.0 * SVG_MARGIN;
+        let plot_height = SVG_HEIGHT - 2.0 * SVG_MARGIN;
+
+        let to_x = |intensity: f64| {
+            SVG_MARGIN + (intensity.log10() - x_min) / (x_max - x_min) * plot_width
+        };
+        let to_y = |flops: f64| {
+            SVG_HEIGHT - SVG_MARGIN - (flops.log10() - y_min) / (y_max - y_min) * plot_height
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+            SVG_WIDTH, SVG_HEIGHT
+        );
+
+        svg.push_str(&format!(
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+            SVG_WIDTH, SVG_HEIGHT
+        ));
+
+        // grid lines and labels at every decade
+        for decade in x_min as i32..=x_max as i32 {
+            let x = to_x(10f64.powi(decade));
+
+            svg.push_str(&format!(
+                "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{0:.1}\" y2=\"{2:.1}\" stroke=\"#ddd\"/>\n",
+                x,
+                SVG_MARGIN,
+                SVG_HEIGHT - SVG_MARGIN
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">1e{}</text>\n",
+                x,
+                SVG_HEIGHT - SVG_MARGIN + 18.0,
+                decade
+            ));
+        }
+
+        for decade in y_min as i32..=y_max as i32 {
+            let y = to_y(10f64.powi(decade));
+
+            svg.push_str(&format!(
+                "<line x1=\"{1:.1}\" y1=\"{0:.1}\" x2=\"{2:.1}\" y2=\"{0:.1}\" stroke=\"#ddd\"/>\n",
+                y,
+                SVG_MARGIN,
+                SVG_WIDTH - SVG_MARGIN
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">1e{}</text>\n",
+                SVG_MARGIN - 6.0,
+                y + 4.0,
+                decade
+            ));
+        }
+
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">arithmetic intensity (FLOP/byte)</text>\n",
+            SVG_WIDTH / 2.0,
+            SVG_HEIGHT - 20.0
+        ));
+        svg.push_str(&format!(
+            "<text x=\"20\" y=\"{0:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 20 {0:.1})\">FLOP/s</text>\n",
+            SVG_HEIGHT / 2.0
+        ));
+
+        // the memory roof up to the ridge point, the compute roof after it
+        let x_start = 10f64.powf(x_min);
+        let x_end = 10f64.powf(x_max);
+
+        svg.push_str(&format!(
+            "<polyline points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"none\" stroke=\"black\" stroke-width=\"2\"/>\n",
+            to_x(x_start),
+            to_y(self.attainable_flops_per_second(x_start)),
+            to_x(ridge_point),
+            to_y(self.peak_flops_per_second),
+            to_x(x_end),
+            to_y(self.peak_flops_per_second)
+        ));
+
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\">{:.1} GB/s, {:.1} GFLOP/s</text>\n",
+            to_x(ridge_point) + 6.0,
+            to_y(self.peak_flops_per_second) - 8.0,
+            self.peak_bytes_per_second / 1e9,
+            self.peak_flops_per_second / 1e9
+        ));
+
+        for point in points {
+            let x = to_x(point.arithmetic_intensity());
+            let y = to_y(point.flops_per_second());
+
+            svg.push_str(&format!(
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\" fill=\"#d62728\"/>\n",
+                x, y
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+                x + 8.0,
+                y + 4.0,
+                point.name
+            ));
+        }
+
+        svg.push_str("</svg>\n");
+
+        svg
+    }
+}
+
+impl std::fmt::Display for Roofline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "peak bandwidth {:.1} GB/s, peak compute {:.1} GFLOP/s, ridge point {:.2} FLOP/byte",
+            self.peak_bytes_per_second / 1e9,
+            self.peak_flops_per_second / 1e9,
+            self.ridge_point()
+        )?;
+
+        for point in &self.points {
+            let intensity = point.arithmetic_intensity();
+
+            // the share of the roof above the kernel's arithmetic intensity
+            let (bound, efficiency) = match intensity < self.ridge_point() {
+                true => (
+                    "memory",
+                    point.bytes_per_second() / self.peak_bytes_per_second,
+                ),
+                false => (
+                    "compute",
+                    point.flops_per_second() / self.peak_flops_per_second,
+                ),
+            };
+
+            writeln!(
+                f,
+                "  {:<16} {:>8.3} ms {:>10.2} FLOP/byte {:>10.1} GFLOP/s {:>8.1} GB/s {:>5.1}% of the {} roof",
+                point.name,
+                point.gpu_time_ms,
+                intensity,
+                point.flops_per_second() / 1e9,
+                point.bytes_per_second() / 1e9,
+                100.0 * efficiency,
+                bound
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// floating point operations and compulsory bytes of one dispatch on N x N matrices
+fn work(shader_variant: ShaderVariant) -> (f64, f64) {
+    let elements = (constants::N * constants::N) as f64;
+    let element_size = std::mem::size_of::<f32>() as f64;
+
+    match shader_variant {
+        ShaderVariant::StreamCopy => (0.0, 2.0 * elements * element_size),
+        // 16 FMAs of 2 operations per iteration
+        ShaderVariant::FmaLoop => (
+            elements * constants::ROOFLINE_FMA_ITERATIONS as f64 * 32.0,
+            3.0 * elements * element_size,
+        ),
+        _ => (
+            2.0 * elements * constants::N as f64,
+            3.0 * elements * element_size,
+        ),
+    }
+}
+
+fn measure_point(
+    vulkan_data: &VulkanData,
+    shader_variant: ShaderVariant,
+    kernel_config: &KernelConfig,
+) -> Result<RooflinePoint, String> {
+    let gpu_time_ms = super::measure_gpu_time(vulkan_data, shader_variant, kernel_config)?;
+
+    let (flops, bytes) = work(shader_variant);
+
+    Ok(RooflinePoint {
+        name: String::from(shader_variant.name()),
+        flops,
+        bytes,
+        gpu_time_ms,
+    })
+}
+
+// runs the micro-benchmarks and every matrix multiplication kernel the device can run with the
+// current pipeline layout; overwrites the matrix buffers
+pub fn measure_roofline(vulkan_data: &VulkanData) -> Result<Roofline, String> {
+    log::info!("measuring roofline");
+
+    if !vulkan_data.profiler.borrow().timestamps_supported() {
+        return Err(String::from(
+            "the roofline needs timestamps on the compute queue",
+        ));
+    }
+
+    let capabilities = &vulkan_data.device_capabilities;
+
+    let benchmark_config = KernelConfig::default_for(ShaderVariant::StreamCopy, capabilities);
+
+    let stream_copy = measure_point(vulkan_data, ShaderVariant::StreamCopy, &benchmark_config)?;
+    let fma_loop = measure_point(vulkan_data, ShaderVariant::FmaLoop, &benchmark_config)?;
+
+    let mut points = Vec::new();
+
+    for &shader_variant in ShaderVariant::ALL {
+        // instrumented variants time their instrumentation as well
+        if shader_variant.kernel() != shader_variant
+            || capabilities.check_shader_variant(shader_variant).is_err()
+        {
+            continue;
+        }
+
+        // its device extension is only enabled when it is the selected kernel
+        if shader_variant == ShaderVariant::Cooperative
+            && vulkan_data.shader_variant.kernel() != ShaderVariant::Cooperative
+        {
+            continue;
+        }
+
+        let kernel_config = match shader_variant == vulkan_data.shader_variant.kernel() {
+            true => vulkan_data.kernel_config,
+            false => super::load_tuned_config(
+                &vulkan_data.physical_device_properties,
+                capabilities,
+                shader_variant,
+                constants::N,
+            )
+            .unwrap_or_else(|| KernelConfig::default_for(shader_variant, capabilities)),
+        };
+
+        match measure_point(vulkan_data, shader_variant, &kernel_config) {
+            Ok(point) => points.push(point),
+            Err(msg) => log::warn!(
+                "skipping {} in the roofline: {}",
+                shader_variant.name(),
+                msg
+            ),
+        }
+    }
+
+    // the micro-benchmarks are kernels too and show how close to the roofs they got
+    let peak_bytes_per_second = stream_copy.bytes_per_second();
+    let peak_flops_per_second = fma_loop.flops_per_second();
+
+    points.push(stream_copy);
+    points.push(fma_loop);
+
+    Ok(Roofline {
+        peak_bytes_per_second,
+        peak_flops_per_second,
+        points,
+    })
+}
@@ -2,12 +2,24 @@ use ash::vk;
 
 use super::VulkanData;
 
-pub fn submit(vulkan_data: &VulkanData, command_buffer: vk::CommandBuffer) -> Result<(), String> {
+// host instants around vkQueueSubmit, for the profiler
+#[derive(Clone, Copy, Debug)]
+pub struct Submission {
+    pub submitted_at: std::time::Instant,
+    pub returned_at: std::time::Instant,
+}
+
+pub fn submit(
+    vulkan_data: &VulkanData,
+    command_buffer: vk::CommandBuffer,
+) -> Result<Submission, String> {
     let cmd_buffers = [command_buffer];
     let submit_info = vk::SubmitInfo::builder()
         .command_buffers(&cmd_buffers)
         .build();
 
+    let submitted_at = std::time::Instant::now();
+
     unsafe {
         vulkan_data
             .device
@@ -15,5 +27,8 @@ pub fn submit(vulkan_data: &VulkanData, command_buffer: vk::CommandBuffer) -> Re
             .map_err(|_| String::from("failed to submit graphics command buffer"))?
     }
 
-    Ok(())
+    Ok(Submission {
+        submitted_at,
+        returned_at: std::time::Instant::now(),
+    })
 }
@@ -0,0 +1,852 @@
+use ash::vk;
+
+use std::collections::HashMap;
+
+use crate::constants;
+use crate::shader_interface::PushConst;
+
+use super::{MatrixBuffers, MemBuffer, RecordedSequence, VulkanData};
+
+// how a task touches a buffer; synchronization2 flags, the bits used here have the same values
+// in the legacy flags
+#[derive(Clone, Copy)]
+struct BufferAccess {
+    buffer: vk::Buffer,
+    host_mapped: bool,
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    write: bool,
+}
+
+impl BufferAccess {
+    fn new(
+        mem_buffer: &MemBuffer,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+        write: bool,
+    ) -> Self {
+        Self {
+            buffer: mem_buffer.buffer,
+            host_mapped: mem_buffer.host_mapped(),
+            stage,
+            access,
+            write,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Task<'a> {
+    Copy {
+        src: &'a MemBuffer,
+        dst: &'a MemBuffer,
+        size: vk::DeviceSize,
+    },
+    Multiply {
+        buffers: MatrixBuffers<'a>,
+    },
+}
+
+impl Task<'_> {
+    fn accesses(&self) -> Vec<BufferAccess> {
+        match self {
+            Task::Copy { src, dst, .. } => vec![
+                BufferAccess::new(
+                    src,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_READ,
+                    false,
+                ),
+                BufferAccess::new(
+                    dst,
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                    true,
+                ),
+            ],
+            Task::Multiply { buffers } => vec![
+                BufferAccess::new(
+                    buffers.a,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_READ,
+                    false,
+                ),
+                BufferAccess::new(
+                    buffers.b,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_READ,
+                    false,
+                ),
+                BufferAccess::new(
+                    buffers.c,
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_WRITE,
+                    true,
+                ),
+            ],
+        }
+    }
+}
+
+// what happened to a buffer since its last write in the graph
+#[derive(Default)]
+struct BufferState {
+    host_mapped: bool,
+    write_stage: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    // the stages and accesses the last write was made visible to
+    visible_stages: vk::PipelineStageFlags2,
+    visible_access: vk::AccessFlags2,
+    // a later write has to wait for these reads
+    read_stages: vk::PipelineStageFlags2,
+}
+
+#[derive(Clone, Copy)]
+struct BufferBarrier {
+    buffer: vk::Buffer,
+    src_stage: vk::PipelineStageFlags2,
+    src_access: vk::AccessFlags2,
+    dst_stage: vk::PipelineStageFlags2,
+    dst_access: vk::AccessFlags2,
+}
+
+// the barriers a task needs before it runs: reads wait for the last write unless an earlier
+// barrier already made it visible to them, writes wait for the last write and the reads since
+fn derive_barriers(
+    states: &mut HashMap<vk::Buffer, BufferState>,
+    accesses: &[BufferAccess],
+) -> Vec<BufferBarrier> {
+    let mut barriers = Vec::new();
+
+    for access in accesses {
+        let state = states.entry(access.buffer).or_default();
+        state.host_mapped = access.host_mapped;
+
+        if !access.write {
+            let visible = state.visible_stages.contains(access.stage)
+                && state.visible_access.contains(access.access);
+
+            if !state.write_stage.is_empty() && !visible {
+                barriers.push(BufferBarrier {
+                    buffer: access.buffer,
+                    src_stage: state.write_stage,
+                    src_access: state.write_access,
+                    dst_stage: access.stage,
+                    dst_access: access.access,
+                });
+
+                state.visible_stages |= access.stage;
+                state.visible_access |= access.access;
+            }
+
+            state.read_stages |= access.stage;
+            continue;
+        }
+
+        // write after read only needs the execution dependency
+        let src_stage = state.write_stage | state.read_stages;
+
+        if !src_stage.is_empty() {
+            barriers.push(BufferBarrier {
+                buffer: access.buffer,
+                src_stage,
+                src_access: state.write_access,
+                dst_stage: access.stage,
+                dst_access: access.access,
+            });
+        }
+
+        *state = BufferState {
+            host_mapped: access.host_mapped,
+            write_stage: access.stage,
+            write_access: access.access,
+            ..Default::default()
+        };
+    }
+
+    barriers
+}
+
+// host mapped buffers written by the graph are read without a copy
+fn host_barriers(states: &HashMap<vk::Buffer, BufferState>) -> Vec<BufferBarrier> {
+    states
+        .iter()
+        .filter(|(_, state)| state.host_mapped && !state.write_stage.is_empty())
+        .map(|(&buffer, state)| BufferBarrier {
+            buffer,
+            src_stage: state.write_stage,
+            src_access: state.write_access,
+            dst_stage: vk::PipelineStageFlags2::HOST,
+            dst_access: vk::AccessFlags2::HOST_READ,
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct TaskGraphReport {
+    // GPU time in ms of the whole graph
+    pub gpu_time: f32,
+    pub task_count: usize,
+    // buffer memory barriers the graph was recorded with
+    pub barrier_count: usize,
+}
+
+impl std::fmt::Display for TaskGraphReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tasks with {} barriers in {:.3} ms",
+            self.task_count, self.barrier_count, self.gpu_time
+        )
+    }
+}
+
+// copies and multiplications recorded into one command buffer, ordered by the buffers they read
+// and write; work submitted before the graph has completed when it runs, as everywhere else
+#[derive(Clone, Default)]
+pub struct TaskGraph<'a> {
+    tasks: Vec<Task<'a>>,
+}
+
+impl<'a> TaskGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn copy(&mut self, src: &'a MemBuffer, dst: &'a MemBuffer) -> &mut Self {
+        self.tasks.push(Task::Copy {
+            src,
+            dst,
+            size: src.size.min(dst.size),
+        });
+        self
+    }
+
+    // C = A * B with the kernel and configuration of vulkan_data
+    pub fn multiply(&mut self, buffers: MatrixBuffers<'a>) -> &mut Self {
+        self.tasks.push(Task::Multiply { buffers });
+        self
+    }
+
+    pub fn execute(&self, vulkan_data: &VulkanData) -> Result<TaskGraphReport, String> {
+        log::info!("executing task graph of {} tasks", self.tasks.len());
+
+        let _scope = vulkan_data.cpu_scope("task graph");
+
+        self.check(vulkan_data)?;
+
+        self.record_and_submit(vulkan_data)
+    }
+
+    // records the graph once to be submitted many times, see `RecordedSequence`; the buffer
+    // contents and the push constants may change between submissions
+    pub fn record(&self, vulkan_data: &'a VulkanData) -> Result<RecordedSequence<'a>, String> {
+        log::info!("recording task graph of {} tasks", self.tasks.len());
+
+        self.check(vulkan_data)?;
+
+        let descriptor_sets = self.descriptor_sets(vulkan_data)?;
+
+        // kept by the sequence to record it again
+        let graph = self.clone();
+
+        let push_const = PushConst {
+            N: constants::N as u32,
+        };
+
+        RecordedSequence::new(
+            vulkan_data,
+            self.tasks.len(),
+            push_const,
+            move |command_buffer, push_const| {
+                Ok(graph.record_tasks(vulkan_data, command_buffer, &descriptor_sets, push_const))
+            },
+        )
+    }
+
+    // whether the kernel and the buffers can run the multiplications
+    fn check(&self, vulkan_data: &VulkanData) -> Result<(), String> {
+        let shader_variant = vulkan_data.shader_variant;
+
+        let multiply_count = self
+            .tasks
+            .iter()
+            .filter(|task| matches!(task, Task::Multiply { .. }))
+            .count();
+
+        if multiply_count > 0 {
+            // the clocks buffer of the profiling variants is bound per dispatch
+            if shader_variant
+                .bindings()
+                .iter()
+                .any(|b| b.name == super::WORKGROUP_CLOCKS_BINDING_NAME)
+            {
+                return Err(format!(
+                    "{} records workgroup clocks and cannot run in a task graph",
+                    shader_variant.name()
+                ));
+            }
+
+            vulkan_data.kernel_config.check_limits(
+                shader_variant,
+                &vulkan_data.device_capabilities,
+                constants::N,
+            )?;
+        }
+
+        for task in &self.tasks {
+            if let Task::Multiply { buffers } = task {
+                if [buffers.a, buffers.b, buffers.c]
+                    .iter()
+                    .any(|mem_buffer| mem_buffer.size < constants::DATA_SIZE as vk::DeviceSize)
+                {
+                    return Err(format!(
+                        "multiplications need buffers of {} bytes",
+                        constants::DATA_SIZE
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // one cached set per multiplication, they are all bound until the graph completes
+    fn descriptor_sets(&self, vulkan_data: &VulkanData) -> Result<Vec<vk::DescriptorSet>, String> {
+        let shader_bindings = vulkan_data.shader_variant.bindings();
+
+        let mut descriptor_allocator = vulkan_data.descriptor_allocator.borrow_mut();
+
+        self.tasks
+            .iter()
+            .filter_map(|task| match task {
+                Task::Multiply { buffers } => Some(buffers),
+                Task::Copy { .. } => None,
+            })
+            .map(|buffers| {
+                descriptor_allocator.descriptor_set(
+                    &vulkan_data.device,
+                    vulkan_data.descriptor_set_layout,
+                    shader_bindings,
+                    &buffers.bound_buffers(shader_bindings, None)?,
+                )
+            })
+            .collect()
+    }
+
+    fn record_and_submit(&self, vulkan_data: &VulkanData) -> Result<TaskGraphReport, String> {
+        let descriptor_sets = self.descriptor_sets(vulkan_data)?;
+
+        let command_buffer = super::allocate_command_buffer(vulkan_data)?;
+
+        let report = self.record_and_wait(vulkan_data, command_buffer, descriptor_sets);
+
+        // released on every path, like the single dispatches
+        let reset = unsafe {
+            // the work of a failed submission may still be in flight
+            if report.is_err() {
+                let _ = vulkan_data.device.device_wait_idle();
+                vulkan_data.profiler.borrow_mut().discard_gpu_scopes();
+            }
+
+            // free command buffer
+            vulkan_data
+                .device
+                .free_command_buffers(vulkan_data.command_pool, &[command_buffer]);
+
+            // reset command pool
+            vulkan_data
+                .device
+                .reset_command_pool(
+                    vulkan_data.command_pool,
+                    vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+                )
+                .map_err(|_| String::from("failed to reset command pool"))
+        };
+
+        let report = report?;
+        reset?;
+
+        Ok(report)
+    }
+
+    fn record_and_wait(
+        &self,
+        vulkan_data: &VulkanData,
+        command_buffer: vk::CommandBuffer,
+        descriptor_sets: Vec<vk::DescriptorSet>,
+    ) -> Result<TaskGraphReport, String> {
+        let device = &vulkan_data.device;
+
+        super::begin_command_buffer(vulkan_data, command_buffer)?;
+
+        let scope_id = vulkan_data.profiler.borrow_mut().begin_gpu_scope(
+            device,
+            command_buffer,
+            "task graph",
+        )?;
+
+        let push_const = PushConst {
+            N: constants::N as u32,
+        };
+
+        let barrier_count =
+            self.record_tasks(vulkan_data, command_buffer, &descriptor_sets, &push_const);
+
+        unsafe {
+            vulkan_data
+                .profiler
+                .borrow_mut()
+                .end_gpu_scope(device, command_buffer, scope_id);
+
+            device
+                .end_command_buffer(command_buffer)
+                .map_err(|_| String::from("failed to end command buffer"))?;
+        }
+
+        let submission = super::submit(vulkan_data, command_buffer)?;
+
+        unsafe {
+            // wait until the GPU is done with all work
+            device
+                .device_wait_idle()
+                .map_err(|_| String::from("failed to wait device idle"))?;
+        }
+
+        let gpu_spans = vulkan_data
+            .profiler
+            .borrow_mut()
+            .resolve_gpu_scopes(device, &submission)?;
+
+        Ok(TaskGraphReport {
+            gpu_time: gpu_spans.iter().map(|span| span.duration_ms()).sum(),
+            task_count: self.tasks.len(),
+            barrier_count,
+        })
+    }
+
+    // the tasks with their barriers, one descriptor set per multiplication; returns the number
+    // of barriers
+    fn record_tasks(
+        &self,
+        vulkan_data: &VulkanData,
+        command_buffer: vk::CommandBuffer,
+        descriptor_sets: &[vk::DescriptorSet],
+        push_const: &PushConst,
+    ) -> usize {
+        let device = &vulkan_data.device;
+
+        let mut descriptor_sets = descriptor_sets.iter();
+
+        let mut states = HashMap::new();
+        let mut barrier_count = 0;
+
+        let [group_count_x, group_count_y, group_count_z] =
+            vulkan_data.kernel_config.group_count(push_const.N as usize);
+
+        for task in &self.tasks {
+            let barriers = derive_barriers(&mut states, &task.accesses());
+
+            barrier_count += barriers.len();
+            record_barriers(vulkan_data, command_buffer, &barriers);
+
+            match task {
+                Task::Copy { src, dst, size } => unsafe {
+                    device.cmd_copy_buffer(
+                        command_buffer,
+                        src.buffer,
+                        dst.buffer,
+                        &[vk::BufferCopy::builder().size(*size).build()],
+                    );
+                },
+                Task::Multiply { .. } => {
+                    let descriptor_set = *descriptor_sets.next().unwrap();
+
+                    unsafe {
+                        device.cmd_push_constants(
+                            command_buffer,
+                            vulkan_data.pipeline_layout,
+                            vk::ShaderStageFlags::COMPUTE,
+                            0,
+                            push_const.as_bytes(),
+                        );
+
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::COMPUTE,
+                            vulkan_data.pipeline_layout,
+                            0,
+                            &[descriptor_set],
+                            &[],
+                        );
+
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::COMPUTE,
+                            vulkan_data.pipeline,
+                        );
+
+                        device.cmd_dispatch(
+                            command_buffer,
+                            group_count_x,
+                            group_count_y,
+                            group_count_z,
+                        );
+                    }
+                }
+            }
+        }
+
+        let host_barriers = host_barriers(&states);
+
+        barrier_count += host_barriers.len();
+        record_barriers(vulkan_data, command_buffer, &host_barriers);
+
+        barrier_count
+    }
+}
+
+// one barrier command for all buffers, synchronization2 keeps the stages per buffer
+fn record_barriers(
+    vulkan_data: &VulkanData,
+    command_buffer: vk::CommandBuffer,
+    barriers: &[BufferBarrier],
+) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    let device = &vulkan_data.device;
+
+    if vulkan_data.device_capabilities.synchronization2 {
+        let buffer_barriers = synchronization2_barriers(barriers);
+
+        let dependency_info = vk::DependencyInfo::builder()
+            .buffer_memory_barriers(&buffer_barriers)
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+        }
+
+        return;
+    }
+
+    let (src_stage, dst_stage, buffer_barriers) = legacy_barriers(barriers);
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barriers,
+            &[],
+        );
+    }
+}
+
+fn synchronization2_barriers(barriers: &[BufferBarrier]) -> Vec<vk::BufferMemoryBarrier2> {
+    barriers
+        .iter()
+        .map(|barrier| {
+            vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(barrier.src_stage)
+                .src_access_mask(barrier.src_access)
+                .dst_stage_mask(barrier.dst_stage)
+                .dst_access_mask(barrier.dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(barrier.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        })
+        .collect()
+}
+
+// without synchronization2 the stages are merged into the one barrier command
+fn legacy_barriers(
+    barriers: &[BufferBarrier],
+) -> (
+    vk::PipelineStageFlags,
+    vk::PipelineStageFlags,
+    Vec<vk::BufferMemoryBarrier>,
+) {
+    let legacy_stage =
+        |stage: vk::PipelineStageFlags2| vk::PipelineStageFlags::from_raw(stage.as_raw() as u32);
+    let legacy_access =
+        |access: vk::AccessFlags2| vk::AccessFlags::from_raw(access.as_raw() as u32);
+
+    let (src_stage, dst_stage) = barriers.iter().fold(
+        (
+            vk::PipelineStageFlags::empty(),
+            vk::PipelineStageFlags::empty(),
+        ),
+        |(src_stage, dst_stage), barrier| {
+            (
+                src_stage | legacy_stage(barrier.src_stage),
+                dst_stage | legacy_stage(barrier.dst_stage),
+            )
+        },
+    );
+
+    let buffer_barriers = barriers
+        .iter()
+        .map(|barrier| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access))
+                .dst_access_mask(legacy_access(barrier.dst_access))
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(barrier.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()
+        })
+        .collect();
+
+    (src_stage, dst_stage, buffer_barriers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    const A: u64 = 1;
+    const B: u64 = 2;
+    const C: u64 = 3;
+
+    fn copy_read(buffer: u64) -> BufferAccess {
+        access(
+            buffer,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+            false,
+        )
+    }
+
+    fn copy_write(buffer: u64) -> BufferAccess {
+        access(
+            buffer,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            true,
+        )
+    }
+
+    fn shader_read(buffer: u64) -> BufferAccess {
+        access(
+            buffer,
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_READ,
+            false,
+        )
+    }
+
+    fn shader_write(buffer: u64) -> BufferAccess {
+        access(
+            buffer,
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_WRITE,
+            true,
+        )
+    }
+
+    fn access(
+        buffer: u64,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+        write: bool,
+    ) -> BufferAccess {
+        BufferAccess {
+            buffer: vk::Buffer::from_raw(buffer),
+            host_mapped: false,
+            stage,
+            access,
+            write,
+        }
+    }
+
+    fn host_mapped(access: BufferAccess) -> BufferAccess {
+        BufferAccess {
+            host_mapped: true,
+            ..access
+        }
+    }
+
+    // the accesses of a multiplication C = A * B
+    fn multiply(a: u64, b: u64, c: u64) -> Vec<BufferAccess> {
+        vec![shader_read(a), shader_read(b), shader_write(c)]
+    }
+
+    #[test]
+    fn dispatch_reading_a_copy_waits_for_it() {
+        let mut states = HashMap::new();
+
+        assert!(derive_barriers(&mut states, &[copy_read(B), copy_write(A)]).is_empty());
+
+        let barriers = derive_barriers(&mut states, &multiply(A, B, C));
+
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].buffer, vk::Buffer::from_raw(A));
+        assert!(barriers[0].src_stage == vk::PipelineStageFlags2::TRANSFER);
+        assert!(barriers[0].src_access == vk::AccessFlags2::TRANSFER_WRITE);
+        assert!(barriers[0].dst_stage == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[0].dst_access == vk::AccessFlags2::SHADER_READ);
+
+        // the copy is already visible to the shaders
+        assert!(derive_barriers(&mut states, &[shader_read(A)]).is_empty());
+    }
+
+    #[test]
+    fn copy_overwriting_a_dispatch_input_waits_for_the_reads() {
+        let mut states = HashMap::new();
+
+        derive_barriers(&mut states, &multiply(A, B, C));
+
+        let barriers = derive_barriers(&mut states, &[copy_read(C), copy_write(A)]);
+
+        // C was written by the dispatch, A only read
+        assert_eq!(barriers.len(), 2);
+        assert_eq!(barriers[0].buffer, vk::Buffer::from_raw(C));
+        assert!(barriers[0].src_access == vk::AccessFlags2::SHADER_WRITE);
+
+        assert_eq!(barriers[1].buffer, vk::Buffer::from_raw(A));
+        assert!(barriers[1].src_stage == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[1].src_access == vk::AccessFlags2::empty());
+        assert!(barriers[1].dst_stage == vk::PipelineStageFlags2::TRANSFER);
+        assert!(barriers[1].dst_access == vk::AccessFlags2::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn write_after_write_and_read_waits_for_both() {
+        let mut states = HashMap::new();
+
+        derive_barriers(&mut states, &[copy_write(A)]);
+        derive_barriers(&mut states, &[shader_read(A)]);
+
+        let barriers = derive_barriers(&mut states, &[copy_write(A)]);
+
+        assert_eq!(barriers.len(), 1);
+        assert!(
+            barriers[0].src_stage
+                == vk::PipelineStageFlags2::TRANSFER | vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+        assert!(barriers[0].src_access == vk::AccessFlags2::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn consecutive_writes_are_ordered() {
+        let mut states = HashMap::new();
+
+        derive_barriers(&mut states, &multiply(A, B, C));
+
+        let barriers = derive_barriers(&mut states, &multiply(A, B, C));
+
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].buffer, vk::Buffer::from_raw(C));
+        assert!(barriers[0].src_stage == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[0].src_access == vk::AccessFlags2::SHADER_WRITE);
+        assert!(barriers[0].dst_access == vk::AccessFlags2::SHADER_WRITE);
+    }
+
+    #[test]
+    fn reads_do_not_wait_for_each_other() {
+        let mut states = HashMap::new();
+
+        assert!(derive_barriers(&mut states, &[shader_read(A), shader_read(B)]).is_empty());
+        assert!(derive_barriers(&mut states, &[copy_read(A), copy_write(C)]).is_empty());
+        assert!(derive_barriers(&mut states, &[shader_read(A)]).is_empty());
+    }
+
+    #[test]
+    fn host_mapped_outputs_are_made_visible_to_the_host() {
+        let mut states = HashMap::new();
+
+        derive_barriers(
+            &mut states,
+            &[
+                host_mapped(shader_read(A)),
+                shader_read(B),
+                host_mapped(shader_write(C)),
+            ],
+        );
+        derive_barriers(&mut states, &[host_mapped(copy_read(C)), copy_write(B)]);
+
+        // A is only read and B is not mapped
+        let barriers = host_barriers(&states);
+
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].buffer, vk::Buffer::from_raw(C));
+        assert!(barriers[0].src_stage == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[0].src_access == vk::AccessFlags2::SHADER_WRITE);
+        assert!(barriers[0].dst_stage == vk::PipelineStageFlags2::HOST);
+        assert!(barriers[0].dst_access == vk::AccessFlags2::HOST_READ);
+    }
+
+    #[test]
+    fn synchronization2_keeps_the_stages_per_buffer() {
+        let mut states = HashMap::new();
+
+        derive_barriers(&mut states, &[copy_write(A), shader_write(B)]);
+
+        let barriers = synchronization2_barriers(&derive_barriers(
+            &mut states,
+            &[copy_read(B), shader_read(A)],
+        ));
+
+        assert_eq!(barriers.len(), 2);
+
+        assert_eq!(barriers[0].buffer, vk::Buffer::from_raw(B));
+        assert!(barriers[0].src_stage_mask == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[0].src_access_mask == vk::AccessFlags2::SHADER_WRITE);
+        assert!(barriers[0].dst_stage_mask == vk::PipelineStageFlags2::TRANSFER);
+        assert!(barriers[0].dst_access_mask == vk::AccessFlags2::TRANSFER_READ);
+
+        assert_eq!(barriers[1].buffer, vk::Buffer::from_raw(A));
+        assert!(barriers[1].src_stage_mask == vk::PipelineStageFlags2::TRANSFER);
+        assert!(barriers[1].src_access_mask == vk::AccessFlags2::TRANSFER_WRITE);
+        assert!(barriers[1].dst_stage_mask == vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert!(barriers[1].dst_access_mask == vk::AccessFlags2::SHADER_READ);
+    }
+
+    #[test]
+    fn legacy_barriers_merge_the_stages() {
+        let mut states = HashMap::new();
+
+        derive_barriers(&mut states, &[copy_write(A), host_mapped(shader_write(B))]);
+
+        let mut barriers = derive_barriers(&mut states, &[copy_read(B), shader_read(A)]);
+        barriers.push(BufferBarrier {
+            buffer: vk::Buffer::from_raw(B),
+            src_stage: vk::PipelineStageFlags2::TRANSFER,
+            src_access: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage: vk::PipelineStageFlags2::HOST,
+            dst_access: vk::AccessFlags2::HOST_READ,
+        });
+
+        let (src_stage, dst_stage, buffer_barriers) = legacy_barriers(&barriers);
+
+        let stages = vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER;
+
+        assert!(src_stage == stages);
+        assert!(dst_stage == stages | vk::PipelineStageFlags::HOST);
+
+        assert_eq!(buffer_barriers.len(), 3);
+
+        assert_eq!(buffer_barriers[0].buffer, vk::Buffer::from_raw(B));
+        assert!(buffer_barriers[0].src_access_mask == vk::AccessFlags::SHADER_WRITE);
+        assert!(buffer_barriers[0].dst_access_mask == vk::AccessFlags::TRANSFER_READ);
+
+        assert_eq!(buffer_barriers[1].buffer, vk::Buffer::from_raw(A));
+        assert!(buffer_barriers[1].src_access_mask == vk::AccessFlags::TRANSFER_WRITE);
+        assert!(buffer_barriers[1].dst_access_mask == vk::AccessFlags::SHADER_READ);
+
+        assert!(buffer_barriers[2].src_access_mask == vk::AccessFlags::TRANSFER_WRITE);
+        assert!(buffer_barriers[2].dst_access_mask == vk::AccessFlags::HOST_READ);
+    }
+}
@@ -0,0 +1,111 @@
+use ash::extensions::ext;
+use ash::vk;
+
+use std::time::{Duration, Instant};
+
+// host clocks a device timestamp can be calibrated against, the one Instant uses comes first
+const HOST_TIME_DOMAINS: [vk::TimeDomainEXT; 3] = [
+    vk::TimeDomainEXT::CLOCK_MONOTONIC,
+    vk::TimeDomainEXT::QUERY_PERFORMANCE_COUNTER,
+    vk::TimeDomainEXT::CLOCK_MONOTONIC_RAW,
+];
+
+// a device timestamp and the host instant it was taken at
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub gpu_ns: f64,
+    pub instant: Instant,
+    // how far `instant` may be off
+    pub uncertainty: Duration,
+}
+
+impl Calibration {
+    // the instant a device timestamp, converted to ns, corresponds to
+    pub fn instant_at(&self, gpu_ns: f64) -> Instant {
+        let offset = Duration::from_nanos((gpu_ns - self.gpu_ns).abs() as u64);
+
+        match gpu_ns >= self.gpu_ns {
+            true => self.instant + offset,
+            false => self.instant.checked_sub(offset).unwrap_or(self.instant),
+        }
+    }
+}
+
+// correlates timestamp query results with the host monotonic clock through
+// VK_EXT_calibrated_timestamps
+pub struct TimestampCalibrator {
+    calibrated_timestamps: ext::CalibratedTimestamps,
+    device: vk::Device,
+    host_time_domain: Option<vk::TimeDomainEXT>,
+    timestamp_period: f32,
+}
+
+impl TimestampCalibrator {
+    // None when the device timestamps cannot be calibrated
+    pub fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        device: &ash::Device,
+        time_domains: &[vk::TimeDomainEXT],
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Option<Self> {
+        log::info!("creating timestamp calibrator");
+
+        if !time_domains.contains(&vk::TimeDomainEXT::DEVICE) {
+            log::info!("device timestamps cannot be calibrated");
+            return None;
+        }
+
+        let host_time_domain = HOST_TIME_DOMAINS
+            .iter()
+            .copied()
+            .find(|domain| time_domains.contains(domain));
+
+        Some(Self {
+            calibrated_timestamps: ext::CalibratedTimestamps::new(entry, instance),
+            device: device.handle(),
+            host_time_domain,
+            timestamp_period: properties.limits.timestamp_period,
+        })
+    }
+
+    // Instant cannot be built from a raw host clock value, so the call is bracketed with
+    // Instant::now() and the midpoint is used; the host domain is only sampled for the deviation
+    pub fn calibrate(&self) -> Result<Calibration, String> {
+        let mut infos = vec![vk::CalibratedTimestampInfoEXT::builder()
+            .time_domain(vk::TimeDomainEXT::DEVICE)
+            .build()];
+
+        if let Some(host_time_domain) = self.host_time_domain {
+            infos.push(
+                vk::CalibratedTimestampInfoEXT::builder()
+                    .time_domain(host_time_domain)
+                    .build(),
+            );
+        }
+
+        let before = Instant::now();
+
+        let (timestamps, max_deviation) = unsafe {
+            self.calibrated_timestamps
+                .get_calibrated_timestamps(self.device, &infos)
+                .map_err(|_| String::from("failed to get calibrated timestamps"))?
+        };
+
+        let after = Instant::now();
+
+        let bracket = (after - before) / 2;
+
+        // a single deviation is returned for all the domains
+        let deviation = match self.host_time_domain {
+            Some(_) => Duration::from_nanos(max_deviation[0]),
+            None => Duration::ZERO,
+        };
+
+        Ok(Calibration {
+            gpu_ns: timestamps[0] as f64 * self.timestamp_period as f64,
+            instant: before + bracket,
+            uncertainty: bracket + deviation,
+        })
+    }
+}
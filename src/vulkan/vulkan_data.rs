@@ -1,14 +1,24 @@
 use ash::vk;
 
+use std::cell::RefCell;
+
 use crate::constants;
+use crate::shader_variants::ShaderVariant;
 
-use super::{begin_command_buffer, update_descriptor_set};
+use super::{
+    CpuScope, DebugMessenger, DescriptorAllocator, DeviceCapabilities, DispatchReport,
+    EnabledFeatures, KernelConfig, MatrixBuffers, MemoryBudget, PerformanceCounters, Profiler,
+    Validation,
+};
 
 pub struct VulkanData {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
+    pub validation: Validation,
+    pub debug_messenger: Option<DebugMessenger>,
     pub physical_device: vk::PhysicalDevice,
     pub physical_device_properties: vk::PhysicalDeviceProperties,
+    pub device_capabilities: DeviceCapabilities,
     pub queue_family: u32,
     pub device: ash::Device,
     pub debug_utils: super::DebugUtils,
@@ -18,96 +28,292 @@ pub struct VulkanData {
     pub mem_buffer_c: super::MemBuffer,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout: vk::PipelineLayout,
+    pub shader_variant: ShaderVariant,
+    pub kernel_config: KernelConfig,
     pub pipeline: vk::Pipeline,
     pub command_pool: vk::CommandPool,
-    pub descriptor_pool: vk::DescriptorPool,
-    pub query_pool: vk::QueryPool,
+    // descriptor sets of the bound buffers, kept between dispatches
+    pub descriptor_allocator: RefCell<DescriptorAllocator>,
+    pub profiler: RefCell<Profiler>,
+    // counts the compute shader invocations of a dispatch when the device supports it
+    pub statistics_query_pool: Option<vk::QueryPool>,
+    // hardware counters, only set after enable_performance_counters
+    pub performance_counters: Option<PerformanceCounters>,
 }
 
 impl VulkanData {
     pub fn new<'a, 'b>(
         required_instance_extensions: &Vec<&'a std::ffi::CStr>,
         required_device_extensions: &Vec<&'b std::ffi::CStr>,
+        mut shader_variant: ShaderVariant,
+        mut validation: Validation,
     ) -> Result<Self, String> {
         let entry = super::create_entry();
         super::check_instance_version(&entry)?;
         super::check_required_instance_extensions(&entry, required_instance_extensions)?;
-        let instance = super::create_instance(&entry, required_instance_extensions)?;
+
+        // validation is skipped rather than failing when the layer is not installed
+        if validation != Validation::Disabled && !super::is_validation_layer_supported(&entry) {
+            log::warn!(
+                "layer {:?} is not installed, continuing without validation",
+                super::validation_layer_name()
+            );
+            validation = Validation::Disabled;
+        }
+
+        if validation == Validation::DebugPrintf && !super::is_debug_printf_supported(&entry) {
+            log::warn!(
+                "instance extension {:?} is not supported, debug printf output is not captured",
+                vk::ExtValidationFeaturesFn::name()
+            );
+            validation = Validation::Enabled;
+        }
+
+        let layers = match validation {
+            Validation::Disabled => Vec::new(),
+            _ => vec![super::validation_layer_name()],
+        };
+
+        let mut instance_extensions = required_instance_extensions.clone();
+        let mut validation_features = Vec::new();
+
+        if validation == Validation::DebugPrintf {
+            instance_extensions.push(vk::ExtValidationFeaturesFn::name());
+            validation_features.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+        }
+
+        let instance =
+            super::create_instance(&entry, &instance_extensions, &layers, &validation_features)?;
+
+        let debug_messenger = match validation {
+            Validation::Disabled => None,
+            _ => Some(DebugMessenger::new(&entry, &instance)?),
+        };
+
         let physical_device = super::get_physical_device(&instance, &required_device_extensions)?;
 
         let physical_device_properties =
             super::get_physical_device_properties(&instance, physical_device);
 
+        let device_capabilities = super::get_device_capabilities(
+            &entry,
+            &instance,
+            physical_device,
+            &physical_device_properties,
+        );
+
+        // kernels relying on optional device features fall back to the tiled one, profiling
+        // kernels to one that is still profiled; the cooperative matrix kernel also needs a
+        // shape it can use within the device limits
+        let supported = device_capabilities
+            .check_shader_variant(shader_variant)
+            .and_then(|_| match shader_variant {
+                ShaderVariant::Cooperative => {
+                    let kernel_config =
+                        KernelConfig::default_for(shader_variant, &device_capabilities);
+
+                    kernel_config.check_limits(shader_variant, &device_capabilities, constants::N)
+                }
+                _ => Ok(()),
+            });
+
+        if let Err(msg) = supported {
+            let fallback = match shader_variant {
+                ShaderVariant::TiledClocksSm => ShaderVariant::TiledClocks,
+                _ => ShaderVariant::Tiled,
+            };
+
+            log::warn!(
+                "{}, falling back from shader variant {} to {}",
+                msg,
+                shader_variant.name(),
+                fallback.name()
+            );
+            shader_variant = fallback;
+        }
+
+        let mut device_extensions = required_device_extensions.clone();
+
+        if shader_variant == ShaderVariant::Cooperative {
+            device_extensions.push(vk::NvCooperativeMatrixFn::name());
+        }
+
+        let subgroup_size_control = device_capabilities.subgroup_size_control.as_ref();
+
+        if subgroup_size_control.is_some_and(|control| control.extension_required) {
+            device_extensions.push(vk::ExtSubgroupSizeControlFn::name());
+        }
+
+        // lets the profiler put GPU timestamps on the host timeline
+        if device_capabilities
+            .calibrated_time_domains
+            .contains(&vk::TimeDomainEXT::DEVICE)
+        {
+            device_extensions.push(vk::ExtCalibratedTimestampsFn::name());
+        }
+
+        let shader_sm_builtins = shader_variant == ShaderVariant::TiledClocksSm;
+
+        if shader_sm_builtins {
+            device_extensions.push(vk::NvShaderSmBuiltinsFn::name());
+        }
+
+        if device_capabilities.performance_query {
+            device_extensions.push(vk::KhrPerformanceQueryFn::name());
+        }
+
+        if device_capabilities.memory_budget {
+            device_extensions.push(vk::ExtMemoryBudgetFn::name());
+        }
+
+        if device_capabilities
+            .imported_host_pointer_alignment
+            .is_some()
+        {
+            device_extensions.push(vk::ExtExternalMemoryHostFn::name());
+        }
+
         let queue_family = super::get_queue_family(&instance, physical_device)?;
 
         let device = super::create_logical_device(
             &instance,
             physical_device,
             queue_family,
-            &required_device_extensions,
+            &device_extensions,
+            &EnabledFeatures {
+                cooperative_matrix: shader_variant == ShaderVariant::Cooperative,
+                subgroup_size_control,
+                shader_sm_builtins,
+                pipeline_statistics_query: device_capabilities.pipeline_statistics_query,
+                performance_query: device_capabilities.performance_query,
+                synchronization2: device_capabilities.synchronization2,
+            },
         )?;
 
         let debug_utils = super::DebugUtils::new(&entry, &instance, device.handle());
 
         let queue = super::get_queue(&device, queue_family);
 
-        // buffer a
-        let mem_buffer_a = super::create_mem_buffer(
+        // the matrices are refused as a whole rather than failing on one of the buffers
+        let matrices_size = 3 * constants::DATA_SIZE as vk::DeviceSize;
+
+        let memory_budget = super::get_memory_budget(
             &instance,
             physical_device,
-            &device,
-            constants::DATA_SIZE as vk::DeviceSize,
+            device_capabilities.memory_budget,
+        );
+
+        log::info!("{}", memory_budget);
+
+        if memory_budget.concurrent_jobs(matrices_size) == 0 {
+            return Err(format!(
+                "the matrices need {} bytes of device local memory, more than any heap has left\n{}",
+                matrices_size, memory_budget
+            ));
+        }
+
+        let create_matrix_buffer = |usage| {
+            super::create_matrix_mem_buffer(
+                &instance,
+                physical_device,
+                &device,
+                &device_capabilities,
+                constants::DATA_SIZE as vk::DeviceSize,
+                usage,
+            )
+        };
+
+        // buffer a
+        let mem_buffer_a = create_matrix_buffer(
             vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?;
 
         debug_utils.set_name(mem_buffer_a.buffer, "matrix A buffer");
         debug_utils.set_name(mem_buffer_a.device_memory, "matrix A device memory");
 
         // buffer b
-        let mem_buffer_b = super::create_mem_buffer(
-            &instance,
-            physical_device,
-            &device,
-            constants::DATA_SIZE as vk::DeviceSize,
+        let mem_buffer_b = create_matrix_buffer(
             vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?;
 
         debug_utils.set_name(mem_buffer_b.buffer, "matrix B buffer");
         debug_utils.set_name(mem_buffer_b.device_memory, "matrix B device memory");
 
         // buffer c
-        let mem_buffer_c = super::create_mem_buffer(
-            &instance,
-            physical_device,
-            &device,
-            constants::DATA_SIZE as vk::DeviceSize,
+        let mem_buffer_c = create_matrix_buffer(
             vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?;
 
         debug_utils.set_name(mem_buffer_c.buffer, "matrix C buffer");
         debug_utils.set_name(mem_buffer_c.device_memory, "matrix C device memory");
 
+        log::info!(
+            "host mapped matrices: a {}, b {}, c {}",
+            mem_buffer_a.host_mapped(),
+            mem_buffer_b.host_mapped(),
+            mem_buffer_c.host_mapped()
+        );
+
         // shader module
-        let shader_module =
-            super::create_shader_module(&device, std::path::Path::new("shaders/shader.comp.spv"))?;
+        log::info!("using shader variant {}", shader_variant.name());
+
+        let shader_module = super::create_shader_module(&device, shader_variant.spv_path())?;
 
         debug_utils.set_name(shader_module, "shader module");
 
+        // the buffers are bound to the variant's bindings in order, profiling kernels also have
+        // the workgroup clocks binding
+        let shader_bindings = shader_variant.bindings();
+
+        let matrix_binding_count = shader_bindings
+            .iter()
+            .filter(|b| b.name != super::WORKGROUP_CLOCKS_BINDING_NAME)
+            .count();
+
+        if matrix_binding_count != 3 || shader_bindings.iter().any(|b| b.set != 0) {
+            return Err(format!(
+                "shader variant {} does not match the matrix buffers: {:?}",
+                shader_variant.name(),
+                shader_bindings.iter().map(|b| b.name).collect::<Vec<_>>()
+            ));
+        }
+
         // descriptor set layout
-        let descriptor_set_layout = super::create_descriptor_set_layout(&device)?;
+        let descriptor_set_layout = super::create_descriptor_set_layout(&device, shader_bindings)?;
 
         debug_utils.set_name(descriptor_set_layout, "decriptor set layout");
 
         // pipeline layout
-        let pipeline_layout = super::create_pipeline_layout(&device, descriptor_set_layout)?;
+        let pipeline_layout = super::create_pipeline_layout(
+            &device,
+            descriptor_set_layout,
+            shader_variant.push_constant_size(),
+        )?;
 
         debug_utils.set_name(pipeline_layout, "pipeline layout");
 
+        // kernel configuration, tuned for this device if available
+        let kernel_config = match super::load_tuned_config(
+            &physical_device_properties,
+            &device_capabilities,
+            shader_variant.kernel(),
+            constants::N,
+        ) {
+            Some(config) => {
+                log::info!("using autotuned kernel configuration {:?}", config);
+                config
+            }
+            None => KernelConfig::default_for(shader_variant, &device_capabilities),
+        };
+
         // pipeline
-        let pipeline = super::create_pipeline(&device, shader_module, pipeline_layout)?;
+        let pipeline = super::create_pipeline(
+            &device,
+            shader_module,
+            pipeline_layout,
+            shader_variant,
+            &kernel_config,
+        )?;
 
         debug_utils.set_name(pipeline, "pipeline");
 
@@ -121,21 +327,45 @@ impl VulkanData {
 
         debug_utils.set_name(command_pool, "command pool");
 
-        // descriptor pool
-        let descriptor_pool = super::create_descriptor_pool(&device)?;
-
-        debug_utils.set_name(descriptor_pool, "descriptor pool");
-
-        // query pool
-        let query_pool = super::create_query_pool(&device)?;
-
-        debug_utils.set_name(query_pool, "query pool");
+        // descriptor allocator
+        let descriptor_allocator =
+            DescriptorAllocator::new(device_capabilities.descriptor_update_template);
+
+        // profiler
+        let profiler = Profiler::new(
+            super::GpuProfiler::new(
+                &instance,
+                physical_device,
+                &physical_device_properties,
+                queue_family,
+            ),
+            super::TimestampCalibrator::new(
+                &entry,
+                &instance,
+                &device,
+                &device_capabilities.calibrated_time_domains,
+                &physical_device_properties,
+            ),
+        );
+
+        // pipeline statistics
+        let statistics_query_pool = match device_capabilities.pipeline_statistics_query {
+            true => Some(super::create_pipeline_statistics_query_pool(&device)?),
+            false => None,
+        };
+
+        if let Some(statistics_query_pool) = statistics_query_pool {
+            debug_utils.set_name(statistics_query_pool, "pipeline statistics query pool");
+        }
 
-        Ok(VulkanData {
+        let vulkan_data = VulkanData {
             entry,
             instance,
+            validation,
+            debug_messenger,
             physical_device,
             physical_device_properties,
+            device_capabilities,
             queue_family,
             device,
             debug_utils,
@@ -145,21 +375,59 @@ impl VulkanData {
             mem_buffer_c,
             descriptor_set_layout,
             pipeline_layout,
+            shader_variant,
+            kernel_config,
             pipeline,
             command_pool,
-            descriptor_pool,
-            query_pool,
-        })
+            descriptor_allocator: RefCell::new(descriptor_allocator),
+            profiler: RefCell::new(profiler),
+            statistics_query_pool,
+            performance_counters: None,
+        };
+
+        vulkan_data.check_validation_errors()?;
+
+        Ok(vulkan_data)
+    }
+
+    // times the enclosing block on the CPU timeline of the profiler
+    pub fn cpu_scope(&self, name: &str) -> CpuScope<'_> {
+        Profiler::cpu_scope(&self.profiler, name)
+    }
+
+    // fails with the validation errors reported so far when they are fatal
+    pub fn check_validation_errors(&self) -> Result<(), String> {
+        let errors = match &self.debug_messenger {
+            Some(debug_messenger) => debug_messenger.take_errors(),
+            None => return Ok(()),
+        };
+
+        if errors.is_empty() || self.validation != Validation::FailOnError {
+            return Ok(());
+        }
+
+        Err(format!(
+            "{} validation error(s), first: {}",
+            errors.len(),
+            errors[0]
+        ))
     }
 
     pub fn clean(self) {
         log::info!("cleaning vulkan data");
 
+        self.profiler.into_inner().clean(&self.device);
+
+        if let Some(performance_counters) = self.performance_counters {
+            performance_counters.clean(&self.device);
+        }
+
         unsafe {
-            self.device.destroy_query_pool(self.query_pool, None);
+            if let Some(statistics_query_pool) = self.statistics_query_pool {
+                self.device.destroy_query_pool(statistics_query_pool, None);
+            }
 
-            self.device
-                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.descriptor_allocator.borrow_mut().clean(&self.device);
 
             self.device.destroy_command_pool(self.command_pool, None);
             self.device.destroy_pipeline(self.pipeline, None);
@@ -186,147 +454,184 @@ impl VulkanData {
                 .free_memory(self.mem_buffer_c.device_memory, None);
 
             self.device.destroy_device(None);
+        }
+
+        if let Some(debug_messenger) = self.debug_messenger {
+            debug_messenger.clean();
+        }
+
+        unsafe {
             self.instance.destroy_instance(None);
         }
     }
 
-    pub fn multiply(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>, String> {
-        super::copy_data_to_buffer(self, &self.mem_buffer_a, a)?;
-        super::copy_data_to_buffer(self, &self.mem_buffer_b, b)?;
-
-        let start = std::time::Instant::now();
+    // the buffers multiply, autotune and the roofline run on
+    pub fn matrix_buffers(&self) -> MatrixBuffers<'_> {
+        MatrixBuffers {
+            a: &self.mem_buffer_a,
+            b: &self.mem_buffer_b,
+            c: &self.mem_buffer_c,
+        }
+    }
 
-        let command_buffer = super::allocate_command_buffer(self)?;
-        let descriptor_set = super::allocate_descriptor_set(self)?;
+    // current heap budgets and usage, e.g. to decide how many jobs can run at once
+    pub fn memory_budget(&self) -> MemoryBudget {
+        super::get_memory_budget(
+            &self.instance,
+            self.physical_device,
+            self.device_capabilities.memory_budget,
+        )
+    }
 
-        begin_command_buffer(self, command_buffer)?;
-        update_descriptor_set(self, descriptor_set);
+    // hardware counters hold the device profiling lock, so they are only collected on request
+    pub fn enable_performance_counters(&mut self) -> Result<(), String> {
+        if self.performance_counters.is_some() {
+            return Ok(());
+        }
 
-        unsafe {
-            self.device.cmd_push_constants(
-                command_buffer,
-                self.pipeline_layout,
-                vk::ShaderStageFlags::COMPUTE,
-                0,
-                &(constants::N as u32).to_ne_bytes(),
-            );
+        if !self.device_capabilities.performance_query {
+            return Err(String::from("performance queries are not supported"));
+        }
 
-            self.device.cmd_bind_descriptor_sets(
-                command_buffer,
-                vk::PipelineBindPoint::COMPUTE,
-                self.pipeline_layout,
-                0,
-                &[descriptor_set],
-                &[],
-            );
+        self.performance_counters = Some(PerformanceCounters::new(
+            &self.entry,
+            &self.instance,
+            self.physical_device,
+            &self.device,
+            self.queue_family,
+        )?);
 
-            self.device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::COMPUTE,
-                self.pipeline,
-            );
+        Ok(())
+    }
 
-            const GROUP_COUNT: u32 = (constants::N as u32) / constants::WORKGROUP_SIZE;
+    pub fn set_kernel_config(&mut self, kernel_config: KernelConfig) -> Result<(), String> {
+        kernel_config.check_limits(self.shader_variant, &self.device_capabilities, constants::N)?;
 
-            assert!(
-                GROUP_COUNT
-                    <= self
-                        .physical_device_properties
-                        .limits
-                        .max_compute_work_group_count[0]
-            );
-            assert!(
-                GROUP_COUNT
-                    <= self
-                        .physical_device_properties
-                        .limits
-                        .max_compute_work_group_count[1]
-            );
+        let shader_module =
+            super::create_shader_module(&self.device, self.shader_variant.spv_path())?;
 
-            self.device
-                .cmd_reset_query_pool(command_buffer, self.query_pool, 0, 2);
+        let pipeline = super::create_pipeline(
+            &self.device,
+            shader_module,
+            self.pipeline_layout,
+            self.shader_variant,
+            &kernel_config,
+        );
 
-            self.device.cmd_write_timestamp(
-                command_buffer,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                self.query_pool,
-                0,
-            );
+        unsafe {
+            self.device.destroy_shader_module(shader_module, None);
+        }
 
-            self.device
-                .cmd_dispatch(command_buffer, GROUP_COUNT, GROUP_COUNT, 1);
+        let pipeline = pipeline?;
 
-            self.device.cmd_write_timestamp(
-                command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                self.query_pool,
-                1,
-            );
+        self.debug_utils.set_name(pipeline, "pipeline");
 
-            self.device
-                .end_command_buffer(command_buffer)
-                .map_err(|_| String::from("failed to end command buffer"))?
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
         }
 
-        super::submit(self, command_buffer)?;
+        self.pipeline = pipeline;
+        self.kernel_config = kernel_config;
 
-        unsafe {
-            // wait until the GPU is done with all work
-            self.device
-                .device_wait_idle()
-                .map_err(|_| String::from("failed to wait device idle"))?;
+        Ok(())
+    }
 
-            let mut query_data = [0u64; 2];
+    // finds the fastest kernel configuration for this device and switches to it
+    pub fn autotune(&mut self) -> Result<KernelConfig, String> {
+        let kernel_config = super::autotune(self)?;
+        self.set_kernel_config(kernel_config)?;
 
-            self.device
-                .get_query_pool_results(
-                    self.query_pool,
-                    0,
-                    2,
-                    &mut query_data,
-                    vk::QueryResultFlags::TYPE_64,
-                )
-                .map_err(|_| String::from("failed to get query pool results"))?;
-
-            let timestamp_start = query_data[0];
-            let timestamp_end = query_data[1];
-
-            println!(
-                "GPU timestamp {}",
-                ((timestamp_end - timestamp_start) as f32)
-                    * self.physical_device_properties.limits.timestamp_period
-                    / 1000000.0f32
+        Ok(kernel_config)
+    }
+
+    // returns C along with what the dispatch reported, e.g. its debugPrintfEXT output
+    pub fn multiply(&self, a: &[f32], b: &[f32]) -> Result<(Vec<f32>, DispatchReport), String> {
+        let _scope = self.cpu_scope("multiply");
+
+        super::copy_data_to_buffer(self, &self.mem_buffer_a, 0, a)?;
+        super::copy_data_to_buffer(self, &self.mem_buffer_b, 0, b)?;
+
+        let start = std::time::Instant::now();
+
+        let report = super::dispatch(
+            self,
+            self.shader_variant,
+            self.pipeline,
+            &self.kernel_config,
+            self.matrix_buffers(),
+        )?;
+
+        // the scopes are kept by the profiler, the total goes to the log
+        log::info!("GPU time {} ms", report.gpu_time);
+
+        // also returned in the report
+        if let Some(timing) = &report.submission_timing {
+            log::info!(
+                "submission latency {:.3} ms, queue wait {:.3} ms, execution {:.3} ms, completion latency {:.3} ms",
+                timing.submission_latency_ms,
+                timing.queue_wait_ms,
+                timing.execution_ms,
+                timing.completion_latency_ms
             );
+        }
 
-            // free command buffer
-            self.device
-                .free_command_buffers(self.command_pool, &[command_buffer]);
+        let duration = start.elapsed();
 
-            // reset command pool
-            self.device
-                .reset_command_pool(
-                    self.command_pool,
-                    vk::CommandPoolResetFlags::RELEASE_RESOURCES,
-                )
-                .map_err(|_| String::from("failed to reset command pool"))?;
+        println!("vulkan time {}", duration.as_millis());
 
-            // reset descriptor pool
-            self.device
-                .reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty())
-                .map_err(|_| String::from("failed to reset descriptor pool"))?;
+        // read the data back
+        let data =
+            super::read_data_from_buffer(self, &self.mem_buffer_c, 0, constants::N * constants::N)?;
+
+        self.check_validation_errors()?;
 
-            let duration = start.elapsed();
+        Ok((data, report))
+    }
+}
 
-            println!("vulkan time {}", duration.as_millis());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a buffer without usage flags is invalid, the layer reports it and the next check fails;
+    // skipped without the validation layer or a device
+    #[test]
+    fn fatal_validation_errors_fail_the_check() {
+        if !super::super::is_validation_layer_supported(&super::super::create_entry()) {
+            eprintln!("validation layer is not installed, skipping");
+            return;
+        }
 
-            // read the data back
-            let data = super::read_data_from_buffer(
-                self,
-                &self.mem_buffer_c,
-                constants::DATA_SIZE as vk::DeviceSize,
-            )?;
+        let vulkan_data = match VulkanData::new(
+            &vec![ash::extensions::ext::DebugUtils::name()],
+            &Vec::new(),
+            ShaderVariant::Tiled,
+            Validation::FailOnError,
+        ) {
+            Ok(vulkan_data) => vulkan_data,
+            Err(msg) => {
+                eprintln!("{}, skipping", msg);
+                return;
+            }
+        };
+
+        assert!(vulkan_data.check_validation_errors().is_ok());
+
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(4)
+            .usage(vk::BufferUsageFlags::empty())
+            .build();
 
-            Ok(data)
+        unsafe {
+            if let Ok(buffer) = vulkan_data.device.create_buffer(&create_info, None) {
+                vulkan_data.device.destroy_buffer(buffer, None);
+            }
         }
+
+        let result = vulkan_data.check_validation_errors();
+
+        vulkan_data.clean();
+
+        assert!(result.is_err());
     }
 }
@@ -0,0 +1,370 @@
+use ash::vk;
+
+use super::{HostAccess, MemBuffer, VulkanData};
+
+// block the profiling kernels write their clocks to, see matmul_profile.glsl
+pub const WORKGROUP_CLOCKS_BINDING_NAME: &str = "WorkgroupClocks";
+
+const HISTOGRAM_BIN_COUNT: u64 = 16;
+
+// an entry of the WorkgroupClocks buffer, clocks are in device clock ticks
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkgroupClock {
+    pub start: u64,
+    pub end: u64,
+    // u32::MAX unless the kernel records it
+    pub sm_id: u32,
+    pub recorded: u32,
+}
+
+impl WorkgroupClock {
+    // the counter may wrap during the dispatch
+    pub fn duration(&self) -> u64 {
+        self.end.wrapping_sub(self.start)
+    }
+}
+
+#[derive(Debug)]
+pub struct HistogramBin {
+    pub min_duration: u64,
+    pub max_duration: u64,
+    pub count: usize,
+}
+
+#[derive(Debug)]
+pub struct SmLoad {
+    pub sm_id: u32,
+    pub workgroup_count: usize,
+    pub busy_ticks: u64,
+}
+
+// summary of the workgroup clocks of one dispatch
+#[derive(Debug)]
+pub struct ClockProfile {
+    pub workgroup_count: usize,
+    // workgroups that did not record their clocks
+    pub missing_count: usize,
+    // from the first start to the last end
+    pub span: u64,
+    pub min_duration: u64,
+    pub mean_duration: f64,
+    pub max_duration: u64,
+    pub histogram: Vec<HistogramBin>,
+    // per SM, empty unless the kernel records SM ids
+    pub sm_loads: Vec<SmLoad>,
+}
+
+impl ClockProfile {
+    pub fn new(clocks: &[WorkgroupClock]) -> Self {
+        let recorded = clocks
+            .iter()
+            .filter(|clock| clock.recorded != 0)
+            .collect::<Vec<_>>();
+
+        let durations = recorded
+            .iter()
+            .map(|clock| clock.duration())
+            .collect::<Vec<_>>();
+
+        let min_duration = durations.iter().copied().min().unwrap_or(0);
+        let max_duration = durations.iter().copied().max().unwrap_or(0);
+
+        let mean_duration = match durations.len() {
+            0 => 0.0,
+            count => durations.iter().sum::<u64>() as f64 / count as f64,
+        };
+
+        // ticks relative to one of the workgroups, so a wrapped counter still orders them
+        let reference = recorded.first().map_or(0, |clock| clock.start);
+        let offset = |ticks: u64| ticks.wrapping_sub(reference) as i64;
+
+        let span = match (
+            recorded.iter().map(|clock| offset(clock.start)).min(),
+            recorded.iter().map(|clock| offset(clock.end)).max(),
+        ) {
+            (Some(start), Some(end)) => end.saturating_sub(start).max(0) as u64,
+            _ => 0,
+        };
+
+        // equal width bins between the shortest and the longest workgroup
+        let bin_width = ((max_duration - min_duration) / HISTOGRAM_BIN_COUNT).max(1);
+
+        let mut histogram = Vec::new();
+
+        if !durations.is_empty() {
+            for bin in 0..HISTOGRAM_BIN_COUNT {
+                let bin_min = min_duration + bin * bin_width;
+                let bin_max = match bin + 1 == HISTOGRAM_BIN_COUNT {
+                    true => max_duration,
+                    false => bin_min + bin_width - 1,
+                };
+
+                if bin_min > max_duration {
+                    break;
+                }
+
+                histogram.push(HistogramBin {
+                    min_duration: bin_min,
+                    max_duration: bin_max,
+                    count: durations
+                        .iter()
+                        .filter(|&&duration| duration >= bin_min && duration <= bin_max)
+                        .count(),
+                });
+            }
+        }
+
+        let mut sm_loads = std::collections::BTreeMap::new();
+
+        for clock in recorded.iter().filter(|clock| clock.sm_id != u32::MAX) {
+            let sm_load = sm_loads.entry(clock.sm_id).or_insert(SmLoad {
+                sm_id: clock.sm_id,
+                workgroup_count: 0,
+                busy_ticks: 0,
+            });
+
+            sm_load.workgroup_count += 1;
+            sm_load.busy_ticks += clock.duration();
+        }
+
+        Self {
+            workgroup_count: clocks.len(),
+            missing_count: clocks.len() - recorded.len(),
+            span,
+            min_duration,
+            mean_duration,
+            max_duration,
+            histogram,
+            sm_loads: sm_loads.into_values().collect(),
+        }
+    }
+
+    // busy time of the busiest SM over the mean, 1 when the work is spread evenly
+    pub fn sm_imbalance(&self) -> Option<f64> {
+        let busiest = self.sm_loads.iter().map(|load| load.busy_ticks).max()?;
+
+        let mean = self
+            .sm_loads
+            .iter()
+            .map(|load| load.busy_ticks as f64)
+            .sum::<f64>()
+            / self.sm_loads.len() as f64;
+
+        match mean > 0.0 {
+            true => Some(busiest as f64 / mean),
+            false => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ClockProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} workgroups ({} missing) over {} ticks",
+            self.workgroup_count, self.missing_count, self.span
+        )?;
+        writeln!(
+            f,
+            "workgroup duration min {} mean {:.0} max {} ticks",
+            self.min_duration, self.mean_duration, self.max_duration
+        )?;
+
+        for bin in &self.histogram {
+            writeln!(
+                f,
+                "  {:>10}..={:<10} {}",
+                bin.min_duration, bin.max_duration, bin.count
+            )?;
+        }
+
+        let busiest = self.sm_loads.iter().max_by_key(|load| load.busy_ticks);
+
+        match (busiest, self.sm_imbalance()) {
+            (Some(busiest), Some(imbalance)) => writeln!(
+                f,
+                "{} SMs, busiest / mean busy time {:.3}, busiest SM {} ran {} workgroups",
+                self.sm_loads.len(),
+                imbalance,
+                busiest.sm_id,
+                busiest.workgroup_count
+            ),
+            _ => writeln!(f, "SM ids were not recorded"),
+        }
+    }
+}
+
+// host visible so it can be read without a copy, cleared so missing workgroups can be told apart
+pub fn create_workgroup_clocks_buffer(
+    vulkan_data: &VulkanData,
+    workgroup_count: usize,
+) -> Result<MemBuffer, String> {
+    log::info!("creating workgroup clocks buffer");
+
+    let size = (workgroup_count * std::mem::size_of::<WorkgroupClock>()) as vk::DeviceSize;
+
+    let mem_buffer = super::create_host_mem_buffer(
+        vulkan_data,
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        HostAccess::Readback,
+    )?;
+
+    let mapped_data_ptr = super::map_mem_buffer(vulkan_data, &mem_buffer)?;
+
+    unsafe {
+        std::ptr::write_bytes(mapped_data_ptr.cast::<u8>(), 0, size as usize);
+    }
+
+    let flushed = super::flush_mem_buffer(vulkan_data, &mem_buffer);
+
+    unsafe {
+        vulkan_data.device.unmap_memory(mem_buffer.device_memory);
+    }
+
+    flushed?;
+
+    Ok(mem_buffer)
+}
+
+pub fn read_workgroup_clocks(
+    vulkan_data: &VulkanData,
+    mem_buffer: &MemBuffer,
+    workgroup_count: usize,
+) -> Result<Vec<WorkgroupClock>, String> {
+    let mapped_data_ptr = super::map_mem_buffer(vulkan_data, mem_buffer)?;
+
+    unsafe {
+        let clocks =
+            std::slice::from_raw_parts(mapped_data_ptr.cast::<WorkgroupClock>(), workgroup_count)
+                .to_owned();
+
+        vulkan_data.device.unmap_memory(mem_buffer.device_memory);
+
+        Ok(clocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(start: u64, duration: u64, sm_id: u32) -> WorkgroupClock {
+        WorkgroupClock {
+            start,
+            end: start.wrapping_add(duration),
+            sm_id,
+            recorded: 1,
+        }
+    }
+
+    #[test]
+    fn summarizes_the_durations() {
+        let clocks = [
+            clock(1000, 100, u32::MAX),
+            clock(1050, 150, u32::MAX),
+            clock(1100, 260, u32::MAX),
+            clock(1200, 260, u32::MAX),
+            // never ran
+            WorkgroupClock::default(),
+        ];
+
+        let profile = ClockProfile::new(&clocks);
+
+        assert_eq!(profile.workgroup_count, 5);
+        assert_eq!(profile.missing_count, 1);
+        assert_eq!(profile.span, 460);
+        assert_eq!(profile.min_duration, 100);
+        assert_eq!(profile.max_duration, 260);
+        assert_eq!(profile.mean_duration, 192.5);
+    }
+
+    #[test]
+    fn bins_the_durations_between_the_extremes() {
+        let clocks = [
+            clock(0, 100, u32::MAX),
+            clock(0, 150, u32::MAX),
+            clock(0, 260, u32::MAX),
+            clock(0, 260, u32::MAX),
+        ];
+
+        let histogram = ClockProfile::new(&clocks).histogram;
+
+        assert_eq!(histogram.len(), HISTOGRAM_BIN_COUNT as usize);
+        assert_eq!(histogram.iter().map(|bin| bin.count).sum::<usize>(), 4);
+
+        assert_eq!(histogram[0].min_duration, 100);
+        assert_eq!(histogram[0].max_duration, 109);
+        assert_eq!(histogram[0].count, 1);
+        assert_eq!(histogram[5].min_duration, 150);
+        assert_eq!(histogram[5].count, 1);
+        assert_eq!(histogram[15].min_duration, 250);
+        assert_eq!(histogram[15].max_duration, 260);
+        assert_eq!(histogram[15].count, 2);
+    }
+
+    #[test]
+    fn loads_are_grouped_by_sm() {
+        let clocks = [
+            clock(0, 100, 1),
+            clock(0, 200, 0),
+            clock(100, 300, 1),
+            WorkgroupClock {
+                sm_id: 0,
+                ..WorkgroupClock::default()
+            },
+        ];
+
+        let profile = ClockProfile::new(&clocks);
+
+        assert_eq!(profile.sm_loads.len(), 2);
+        assert_eq!(profile.sm_loads[0].sm_id, 0);
+        assert_eq!(profile.sm_loads[0].workgroup_count, 1);
+        assert_eq!(profile.sm_loads[0].busy_ticks, 200);
+        assert_eq!(profile.sm_loads[1].sm_id, 1);
+        assert_eq!(profile.sm_loads[1].workgroup_count, 2);
+        assert_eq!(profile.sm_loads[1].busy_ticks, 400);
+
+        // 400 over a mean of 300
+        assert_eq!(profile.sm_imbalance(), Some(4.0 / 3.0));
+    }
+
+    #[test]
+    fn no_imbalance_without_sm_ids() {
+        let profile = ClockProfile::new(&[clock(0, 100, u32::MAX), clock(0, 200, u32::MAX)]);
+
+        assert!(profile.sm_loads.is_empty());
+        assert_eq!(profile.sm_imbalance(), None);
+    }
+
+    #[test]
+    fn survives_counter_wraparound() {
+        let clocks = [
+            clock(u64::MAX - 50, 100, 0),
+            clock(u64::MAX - 10, 200, 0),
+            clock(20, 30, 1),
+        ];
+
+        let profile = ClockProfile::new(&clocks);
+
+        assert_eq!(clocks[1].duration(), 200);
+        assert_eq!(profile.min_duration, 30);
+        assert_eq!(profile.max_duration, 200);
+        // from u64::MAX - 50 to 189 after the wrap
+        assert_eq!(profile.span, 240);
+        assert_eq!(profile.sm_loads[0].busy_ticks, 300);
+    }
+
+    #[test]
+    fn empty_without_recorded_clocks() {
+        let profile = ClockProfile::new(&[WorkgroupClock::default(); 3]);
+
+        assert_eq!(profile.workgroup_count, 3);
+        assert_eq!(profile.missing_count, 3);
+        assert_eq!(profile.span, 0);
+        assert_eq!(profile.mean_duration, 0.0);
+        assert!(profile.histogram.is_empty());
+        assert!(profile.sm_loads.is_empty());
+    }
+}